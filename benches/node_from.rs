@@ -20,11 +20,17 @@ fn node_from(c: &mut Criterion) {
         })
     });
 
+    let mut val = format!("{}{}", LOREM, LOREM);
+    for _ in 0..10 {
+        val = format!("{}{}", val, val);
+    }
+    eprintln!(
+        "bigger than max leaf: {} bytes on heap for {} bytes of text",
+        Node::from(val.as_str()).heap_size(),
+        val.len()
+    );
+
     group.bench_function("bigger than max lefa", |b| {
-        let mut val = format!("{}{}", LOREM, LOREM);
-        for _ in 0..10 {
-            val = format!("{}{}", val, val);
-        }
         b.iter(|| {
             let _ = Node::from(val.as_str());
         })
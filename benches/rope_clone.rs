@@ -22,32 +22,41 @@ fn clone(c: &mut Criterion) {
         })
     });
 
-    group.bench_function("Medium rope", |b| {
-        let mut rope = Rope::new();
-
-        for _ in 0..500 {
-            rope.append(LOREM);
-        }
-
+    let mut rope = Rope::new();
+    for _ in 0..500 {
+        rope.append(LOREM);
+    }
+    eprintln!("Medium rope: {} bytes on heap for {} chars", rope.heap_size(), rope.len());
 
+    group.bench_function("Medium rope", |b| {
         b.iter(|| {
             let _ = rope.clone();
         })
     });
 
-    group.bench_function("Large rope", |b| {
-        let mut rope = Rope::new();
+    let mut rope = Rope::new();
+    for _ in 0..2500 {
+        rope.append(LOREM);
+    }
+    eprintln!("Large rope: {} bytes on heap for {} chars", rope.heap_size(), rope.len());
 
-        for _ in 0..2500 {
-            rope.append(LOREM);
-        }
+    group.bench_function("Large rope", |b| {
+        b.iter(|| {
+            let _ = rope.clone();
+        })
+    });
 
+    let mut rope = Rope::new();
+    for _ in 0..2500 {
+        rope = rope.concat(Rope::from("a\n"));
+    }
+    eprintln!("Many short lines: {} bytes on heap for {} chars", rope.heap_size(), rope.len());
 
+    group.bench_function("Many short lines", |b| {
         b.iter(|| {
             let _ = rope.clone();
         })
     });
-
 }
 
 criterion_group!(benches, clone);
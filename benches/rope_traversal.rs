@@ -1,5 +1,5 @@
-use criterion::{criterion_group, criterion_main, Criterion};
-use editorus::rope::{self, rope::Rope};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use editorus::rope::{self, rope::Rope, traverser::Traverser};
 
 static LOREM: &'static str = "lorem ipsum dolor sit amet, consectetur adipiscing elit. sed do eiusmod tempor incididunt ut labore et dolore magna aliqua. ";
 
@@ -51,6 +51,40 @@ fn append(c: &mut Criterion) {
             rope.do_nothing_at();
         })
     });
+
+    let mut rope = Rope::new();
+    for _ in 0..2500 {
+        rope.append(LOREM);
+    }
+    let middle = rope.len() / 2;
+
+    group.bench_function("Typing 100 chars in large rope", |b| {
+        b.iter_batched(
+            || rope.clone(),
+            |mut rope| {
+                for i in 0..100 {
+                    rope.insert(middle + i, "a");
+                }
+                rope
+            },
+            BatchSize::SmallInput,
+        )
+    });
+
+    group.bench_function("Typing 100 chars in large rope with traverser", |b| {
+        b.iter_batched(
+            || rope.clone(),
+            |rope| {
+                let mut traverser = Traverser::new(rope);
+                traverser.seek_char(middle);
+                for _ in 0..100 {
+                    traverser.insert("a");
+                }
+                traverser.into_rope()
+            },
+            BatchSize::SmallInput,
+        )
+    });
 }
 
 criterion_group!(benches, append);
@@ -0,0 +1,13 @@
+#![no_main]
+
+// Applies arbitrary sequences of edits to a rope and to a `String`, comparing them after every
+// step. Run with `cargo +nightly fuzz run rope_ops`; no network is needed once dependencies are
+// in the local cargo cache (`--offline`).
+
+use editorus::rope::harness::{self, Op};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: (String, Vec<Op>)| {
+    let (initial, ops) = input;
+    harness::run(&initial, ops);
+});
@@ -1,7 +1,4 @@
-use crate::{
-    editor::{cursor::ECursor, session::ERow},
-    writer::escapes::EscapeSequence,
-};
+use crate::{editor::cursor::ECursor, rope::rope::Rope, writer::escapes::EscapeSequence};
 use std::io::{Stdout, Write};
 
 // Viewport tell's use what part of the buffer we are currently viewing
@@ -103,7 +100,7 @@ impl Display {
     }
 
     // Refresh the display buffer
-    pub fn refresh(&mut self, data: &[ERow], display_options: DisplayOptions) {
+    pub fn refresh(&mut self, data: &Rope, display_options: DisplayOptions) {
         self.cells = Cells::new(self.viewport.height as usize * self.viewport.width as usize);
 
         let offset_x = if display_options.show_line_numbers {
@@ -114,12 +111,12 @@ impl Display {
         };
 
         let mut idx = 0;
-        let max_lines = data.len().min(self.viewport.height as usize);
+        let line_count = data.line_count();
+        let max_lines = line_count.min(self.viewport.height as usize);
         let start_line = self.viewport.offset_y as usize;
 
-        let printable_lines = data.len().min(start_line + max_lines);
+        let printable_lines = line_count.min(start_line + max_lines);
         for row in start_line..printable_lines {
-
             let display_row = row - start_line;
 
             if display_options.show_line_numbers {
@@ -143,9 +140,9 @@ impl Display {
             }
 
             let start_col = self.viewport.offset_x as usize;
-            let rd_iter = data[row].data.leaf_iter();
-            let char_iter = rd_iter.map(|it| it.chars()).flatten();
-            for (col, c) in char_iter 
+            for (col, c) in data
+                .line_without_ending(row)
+                .chars()
                 .skip(start_col)
                 .take(self.viewport.width as usize - offset_x)
                 .enumerate()
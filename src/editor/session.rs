@@ -1,41 +1,16 @@
 use super::{config::Configuration, cursor::ECursor};
 use crate::{
-    display::display::{Display, Dump, WholeDump},
+    display::display::{Display, Dump, Point, WholeDump},
     rope::rope::Rope,
 };
 use std::{
-    fs::OpenOptions,
-    io::{BufWriter, Read, Stdout, Write},
+    fs::{self, OpenOptions},
+    io::{self, BufWriter, Stdout, Write},
+    path::{Path, PathBuf},
 };
 
-pub struct ERow {
-    pub data: Rope,
-}
-
-impl ERow {
-    fn empty() -> Self {
-        Self { data: Rope::new() }
-    }
-
-    fn new(data: Rope) -> Self {
-        Self { data }
-    }
-
-    pub fn len(&self) -> usize {
-        self.data.len()
-    }
-}
-
-impl From<&str> for ERow {
-    fn from(value: &str) -> Self {
-        Self {
-            data: Rope::from(value),
-        }
-    }
-}
-
 pub struct Session {
-    data: Vec<ERow>,
+    data: Rope,
     display: Display,
     cursor: ECursor,
     dirty: bool,
@@ -45,7 +20,7 @@ pub struct Session {
 impl Session {
     pub fn new(width: u16, height: u16) -> Self {
         let session = Session {
-            data: vec![ERow::empty()],
+            data: Rope::new(),
             display: Display::with_dimensions(width, height),
             cursor: ECursor::at_home(),
             dirty: true,
@@ -59,7 +34,7 @@ impl Session {
         let cursor_offset_x = if config.show_line_numbers { 4 } else { 0 };
 
         let session = Session {
-            data: vec![ERow::empty()],
+            data: Rope::new(),
             display: Display::with_dimensions(width, height),
             cursor: ECursor::with_offset(cursor_offset_x, 0),
             dirty: true,
@@ -69,28 +44,26 @@ impl Session {
     }
 
     pub fn open_file(&mut self, file_path: String) -> Result<(), std::io::Error> {
-        let mut content = String::new();
-        let mut file = OpenOptions::new().read(true).open(&file_path)?;
-        file.read_to_string(&mut content)?;
-        let rows: Vec<ERow> = content
-            .lines()
-            .into_iter()
-            .map(|row| ERow::from(row))
-            .collect();
-        if rows.is_empty() {
-            self.data.push(ERow::empty());
-        } else {
-            self.data = rows;
-        }
+        self.data = Rope::from_file(&file_path)?;
         self.fd = Some(file_path);
         self.rebuild_display();
         Ok(())
     }
 
-    pub fn rows(&self) -> &[ERow] {
+    pub fn data(&self) -> &Rope {
         &self.data
     }
 
+    // Number of chars in given line, without the trailing line break.
+    fn line_len(&self, line: usize) -> usize {
+        self.data.line_without_ending(line).len()
+    }
+
+    // Char index in the buffer under given point.
+    fn char_index(&self, point: &Point) -> usize {
+        self.data.line_to_char(point.y) + point.x
+    }
+
     pub fn cursor(&self) -> &ECursor {
         &self.cursor
     }
@@ -105,7 +78,7 @@ impl Session {
             self.cursor.up();
 
             let point = self.display.point_at(&self.cursor);
-            if point.x > self.data[point.y].len() {
+            if point.x > self.line_len(point.y) {
                 self.fit_to_screen();
             }
             if self.cursor.x == 0 {
@@ -124,27 +97,27 @@ impl Session {
     // Returns (max_number_of_chars, offset)
     fn calculate_last_position(&self) -> (usize, usize) {
         let point = self.display.point_at(&self.cursor);
-        let row = &self.data[point.y];
-        let max_number_of_chars = self.display.width() as usize - self.cursor.offset.0;
-        if row.len() > max_number_of_chars {
-            let offset = (row.len() - max_number_of_chars) as u16 + 1;
-            (max_number_of_chars, offset as usize)
+        let row_len = self.line_len(point.y);
+        let max_number_of_chars = self.display.width() - self.cursor.offset.0;
+        if row_len > max_number_of_chars {
+            let offset = row_len - max_number_of_chars + 1;
+            (max_number_of_chars, offset)
         } else {
-            (row.len() + 1, 0)
+            (row_len + 1, 0)
         }
     }
 
     pub fn cursor_down(&mut self) {
         let point = self.display.point_at(&self.cursor);
 
-        if point.y != self.data.len() - 1 {
+        if point.y != self.data.line_count() - 1 {
             if self.cursor.y == self.display.height() as usize {
                 self.display.viewport.offset_y += 1;
                 self.mark_dirty();
             } else {
                 self.cursor.down();
                 let new_point = self.display.point_at(&self.cursor);
-                if new_point.x > self.data[new_point.y].len() {
+                if new_point.x > self.line_len(new_point.y) {
                     self.fit_to_screen();
                 }
             }
@@ -172,7 +145,7 @@ impl Session {
 
     pub fn cursor_right(&mut self) {
         let point = self.display.point_at(&self.cursor);
-        if point.x < self.data[point.y].len() {
+        if point.x < self.line_len(point.y) {
             if self.cursor.x + self.cursor.offset.0 == self.display.width() as usize {
                 self.display.viewport.offset_x += 1;
                 self.cursor.move_to_line_beginning();
@@ -181,7 +154,7 @@ impl Session {
                 self.cursor.right();
             }
         } else {
-            if point.y == self.data.len() - 1 {
+            if point.y == self.data.line_count() - 1 {
                 return;
             }
             let curr_row = self.cursor.y;
@@ -213,9 +186,15 @@ impl Session {
 
     pub fn insert(&mut self, data: &[u8]) {
         let point = self.display.point_at(&self.cursor);
-        let row = &mut self.data[point.y];
-        let data = std::str::from_utf8(data).unwrap();
-        row.data.insert(point.x, data);
+        let index = self.char_index(&point);
+        let Ok(data) = std::str::from_utf8(data) else {
+            log::warn!("Ignoring input which is not valid UTF-8: {:?}", data);
+            return;
+        };
+        if let Err(err) = self.data.try_insert(index, data) {
+            log::error!("Could not insert at {}: {}", index, err);
+            return;
+        }
         self.cursor_right();
         self.mark_dirty();
     }
@@ -225,13 +204,13 @@ impl Session {
             return;
         }
         let point = self.display.point_at(&self.cursor);
+        let index = self.char_index(&point);
         if self.cursor.at_start() {
             self.cursor_up();
             let (chars, offset) = self.calculate_last_position();
-            let prev_row = self.data.remove(point.y - 1);
-            let curr_row = self.data.remove(point.y - 1);
-            let concat = prev_row.data.concat(curr_row.data);
-            self.data.insert(point.y - 1, ERow::new(concat));
+            // Joins current line with the previous one, removing the whole line ending.
+            let previous_end = self.data.line_to_char(point.y - 1) + self.line_len(point.y - 1);
+            self.data.remove_range(previous_end..index);
 
             // Fit into screen????
             self.cursor.x = chars;
@@ -240,23 +219,15 @@ impl Session {
             self.mark_dirty();
         } else {
             self.cursor.left();
-            let row = &mut self.data[point.y];
-            row.data.remove_at(point.x - 1);
+            self.data.remove_at(index - 1);
         }
         self.mark_dirty();
     }
 
     pub fn new_line(&mut self) {
         let point = self.display.point_at(&self.cursor);
-        let current_row = &self.data[point.y];
-        if point.x != current_row.data.len() {
-            let row = &mut self.data[point.y];
-            let (curr, next) = row.data.split_at(point.x);
-            row.data = curr;
-            self.data.insert(point.y + 1, ERow::new(next));
-        } else {
-            self.data.insert(point.y + 1, ERow::empty());
-        }
+        let index = self.char_index(&point);
+        self.data.insert(index, "\n");
 
         self.cursor_down();
         self.cursor.move_to_line_beginning();
@@ -267,25 +238,23 @@ impl Session {
 
     pub fn delete(&mut self) {
         let point = self.display.point_at(&self.cursor);
+        // At the end of the line this removes the line break, joining it with the next one.
+        if self.is_cursor_at_the_end_of_line() && point.y == self.data.line_count() - 1 {
+            return;
+        }
+        let index = self.char_index(&point);
         if self.is_cursor_at_the_end_of_line() {
-            if self.cursor.y == self.data.len() {
-                return;
-            }
-            let curr_line = self.data.remove(point.y);
-            let next_line = self.data.remove(point.y);
-
-            let new_line = curr_line.data.concat(next_line.data);
-            self.data.insert(point.y, ERow::new(new_line));
+            let next_line = self.data.line_to_char(point.y + 1);
+            self.data.remove_range(index..next_line);
         } else {
-            let row = &mut self.data[point.y];
-            row.data.remove_at(point.x);
+            self.data.remove_at(index);
         }
         self.mark_dirty();
     }
 
     fn is_cursor_at_the_end_of_line(&self) -> bool {
-        self.cursor.x_relative_to_viewport(self.display.viewport())
-            == self.data[self.cursor.y - 1].data.len()
+        let point = self.display.point_at(&self.cursor);
+        point.x == self.line_len(point.y)
     }
 
     pub(crate) fn display_on(&mut self, stdout: &mut Stdout) -> std::io::Result<()> {
@@ -295,22 +264,46 @@ impl Session {
     }
 
     // Handle for empty buffer once we have it
-    pub fn save_file(&self) {
-        if let Some(file_path) = &self.fd {
-            let file = OpenOptions::new()
-                .write(true)
-                .create(true)
-                .truncate(true)
-                .open(file_path)
-                .unwrap();
-            let mut file_writer = BufWriter::new(file);
-            for row in &self.data {
-                file_writer.write_all(row.data.value().as_bytes()).unwrap();
-                // Get line ending per system
-                file_writer.write_all(b"\n").unwrap();
-            }
-            file_writer.flush().unwrap();
+    // A new file is written next to the old one and renamed over it, so that a failed save
+    // leaves the old content intact. A symlink is followed, so that the link stays and its
+    // target is replaced, and the new file gets the permissions of the old one.
+    pub fn save_file(&self) -> io::Result<()> {
+        let Some(file_path) = &self.fd else {
+            return Ok(());
+        };
+        let target = fs::canonicalize(file_path)?;
+        let mut temp_path = target.clone().into_os_string();
+        temp_path.push(".save");
+        let temp_path = PathBuf::from(temp_path);
+
+        let result = self.replace_file(&target, &temp_path);
+        if result.is_err() {
+            // The first error is the one worth reporting, the temp file may not even exist.
+            let _ = fs::remove_file(&temp_path);
+        }
+        result
+    }
+
+    fn replace_file(&self, target: &Path, temp_path: &Path) -> io::Result<()> {
+        let permissions = fs::metadata(target)?.permissions();
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(temp_path)?;
+        let mut file_writer = BufWriter::new(file);
+        self.data.write_to(&mut file_writer)?;
+        file_writer.flush()?;
+        let file = file_writer.get_ref();
+        file.set_permissions(permissions)?;
+        file.sync_all()?;
+        // Where the file cannot be replaced, e.g. as another program holds it open on Windows,
+        // it's overwritten in place. The buffer reads from its own snapshot, not from the file.
+        if fs::rename(temp_path, target).is_err() {
+            fs::copy(temp_path, target)?;
+            fs::remove_file(temp_path)?;
         }
+        Ok(())
     }
 }
 
@@ -352,8 +345,8 @@ mod tests {
 
         session.new_line();
 
-        assert_eq!(session.data[0].data.value(), "asdfś");
-        assert_eq!(session.data[1].data.value(), "qwer");
+        assert_eq!(session.data.line(0), "asdfś");
+        assert_eq!(session.data.line(1), "qwer");
     }
 
     #[test]
@@ -361,7 +354,44 @@ mod tests {
         let mut session = Session::new(50, 50);
         session.open_file("witam.txt".to_string()).unwrap();
 
-        assert_eq!(session.data[0].data.value(), "Witam");
+        assert_eq!(session.data.line(0), "Witam");
+    }
+
+    #[test]
+    fn line_len_skips_crlf() {
+        let path = std::env::temp_dir().join("editorus_line_len_skips_crlf.txt");
+        std::fs::write(&path, "Witam\r\nświecie\r\n").unwrap();
+
+        let mut session = get_session(50, 50);
+        session
+            .open_file(path.to_str().unwrap().to_string())
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(5, session.line_len(0));
+        assert_eq!(7, session.line_len(1));
+        assert_eq!(0, session.line_len(2));
+    }
+
+    #[test]
+    fn joining_crlf_lines_removes_whole_line_ending() {
+        let path = std::env::temp_dir().join("editorus_joining_crlf_lines.txt");
+        std::fs::write(&path, "ab\r\ncd\r\nef").unwrap();
+
+        let mut session = get_session(50, 50);
+        session
+            .open_file(path.to_str().unwrap().to_string())
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        session.cursor_down();
+        session.backspace();
+        assert_eq!("abcd\r\nef", session.data().value());
+
+        session.cursor_right();
+        session.cursor_right();
+        session.delete();
+        assert_eq!("abcdef", session.data().value());
     }
 
     #[test]
@@ -369,7 +399,7 @@ mod tests {
         let mut session = get_session(50, 50);
         session.open_file("witam.txt".to_string()).unwrap();
 
-        assert_eq!(session.data[0].data.value(), "Witam");
+        assert_eq!(session.data.line(0), "Witam");
 
         session.insert(b"N");
         session.insert(b"c");
@@ -382,7 +412,7 @@ mod tests {
         session.delete();
         session.delete();
 
-        assert_eq!(session.data[0].data.value(), "Witam");
+        assert_eq!(session.data.line(0), "Witam");
     }
 
     #[test]
@@ -402,7 +432,7 @@ mod tests {
         session.backspace();
         session.backspace();
 
-        assert_eq!(session.rows().len(), 1);
+        assert_eq!(session.data().line_count(), 1);
     }
 
     #[test]
@@ -435,7 +465,145 @@ mod tests {
             session.delete();
         }
 
-        assert_eq!(session.data[0].data.value(), "");
+        assert_eq!(session.data.line(0), "");
+    }
+
+    #[test]
+    fn invalid_utf8_input_is_ignored() {
+        let mut session = get_session(50, 50);
+
+        session.insert(b"a");
+        session.insert(&[0xC5]);
+
+        assert_eq!(session.data.line(0), "a");
+        assert_eq!(session.cursor().x, 2);
+    }
+
+    #[test]
+    fn new_line_in_the_middle_splits_line() {
+        let mut session = get_session(50, 50);
+        session.open_file("witam.txt".to_string()).unwrap();
+
+        session.cursor_right();
+        session.cursor_right();
+        session.new_line();
+
+        assert_eq!(session.data().line_count(), 3);
+        assert_eq!(session.data.line(0), "Wi");
+        assert_eq!(session.data.line(1), "tam");
+    }
+
+    #[test]
+    fn delete_at_end_of_line_joins_lines() {
+        let mut session = get_session(50, 50);
+
+        session.insert(b"a");
+        session.new_line();
+        session.insert(b"b");
+        session.new_line();
+        session.insert(b"c");
+
+        session.cursor_up();
+        session.cursor_up();
+        session.delete();
+
+        assert_eq!(session.data().line_count(), 2);
+        assert_eq!(session.data.line(0), "ab");
+        assert_eq!(session.data.line(1), "c");
+    }
+
+    #[test]
+    fn save_file_writes_whole_buffer() {
+        let path = std::env::temp_dir().join("editorus_save_file_writes_whole_buffer.txt");
+        std::fs::write(&path, "Witam\nświecie\n").unwrap();
+
+        let mut session = get_session(50, 50);
+        session
+            .open_file(path.to_str().unwrap().to_string())
+            .unwrap();
+        session.insert(b"N");
+        session.save_file().unwrap();
+
+        let saved = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!("NWitam\nświecie\n", saved);
+    }
+
+    #[test]
+    fn save_file_over_file_open_in_session() {
+        let path = std::env::temp_dir().join("editorus_save_file_over_file_open_in_session.txt");
+        std::fs::write(&path, "Witam\nświecie\n".repeat(1000)).unwrap();
+
+        let mut session = get_session(50, 50);
+        session
+            .open_file(path.to_str().unwrap().to_string())
+            .unwrap();
+        session.insert(b"N");
+        session.save_file().unwrap();
+        session.insert(b"M");
+        session.save_file().unwrap();
+
+        let saved = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let expected = format!("NM{}", "Witam\nświecie\n".repeat(1000));
+        assert_eq!(expected, saved);
+        assert_eq!(session.data(), &expected);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn save_file_replaces_symlink_target_and_keeps_permissions() {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+
+        let dir = std::env::temp_dir().join("editorus_save_file_replaces_symlink_target");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+        let target = dir.join("target.txt");
+        let link = dir.join("link.txt");
+        std::fs::write(&target, "Witam\n").unwrap();
+        std::fs::set_permissions(&target, std::fs::Permissions::from_mode(0o640)).unwrap();
+        symlink(&target, &link).unwrap();
+
+        let mut session = get_session(50, 50);
+        session
+            .open_file(link.to_str().unwrap().to_string())
+            .unwrap();
+        session.insert(b"N");
+        session.save_file().unwrap();
+
+        assert!(std::fs::symlink_metadata(&link).unwrap().is_symlink());
+        assert_eq!("NWitam\n", std::fs::read_to_string(&target).unwrap());
+        let mode = std::fs::metadata(&target).unwrap().permissions().mode();
+        assert_eq!(0o640, mode & 0o777);
+        let names: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(2, names.len());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn save_file_reports_error_and_cleans_up() {
+        let dir = std::env::temp_dir().join("editorus_save_file_reports_error_and_cleans_up");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+        let path = dir.join("file.txt");
+        std::fs::write(&path, "Witam\n").unwrap();
+
+        let mut session = get_session(50, 50);
+        session
+            .open_file(path.to_str().unwrap().to_string())
+            .unwrap();
+        session.insert(b"N");
+        // Neither renaming nor copying the new file over a directory works, once it is written.
+        std::fs::remove_file(&path).unwrap();
+        std::fs::create_dir(&path).unwrap();
+        std::fs::write(path.join("inner.txt"), "").unwrap();
+
+        assert!(session.save_file().is_err());
+        assert!(!dir.join("file.txt.save").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
     fn get_session(w: u16, h: u16) -> Session {
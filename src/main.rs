@@ -93,7 +93,9 @@ pub fn run_terminal() -> std::io::Result<()> {
                 session.backspace();
             } else if buf[0] == 23 {
                 // CTRL + W
-                session.save_file();
+                if let Err(err) = session.save_file() {
+                    log::error!("Could not save file: {}", err);
+                }
             } else if buf[0] == 21 {
                 // CTRL + U
             } else {
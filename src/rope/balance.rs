@@ -0,0 +1,124 @@
+use std::sync::Arc;
+
+use super::{internal::Internal, leaf::Leaf, metrics::Metrics, node::Node};
+
+// Height-balanced (AVL-style) joining of subtrees. Heights of two branches of any internal node
+// differ by at most one, which keeps depth of the rope O(log n) after every edit.
+
+// Joins two balanced subtrees, `left_metrics` being the size of the whole `left`. Descends the
+// spine of the taller one, so it's O(height difference).
+// Neighbouring leaves meeting at the bottom are merged if either of them is undersized.
+pub(crate) fn join(left: Arc<Node>, right: Arc<Node>, left_metrics: Metrics) -> Node {
+    let (left_height, right_height) = (left.height(), right.height());
+
+    if left_height > right_height + 1 {
+        let Node::Internal(node) = left.as_ref() else {
+            unreachable!("Leaf cannot be taller than another node");
+        };
+        let [left_left, left_right] = node.branches.clone();
+        let joined = join(left_right, right, left_metrics - node.left_metrics());
+        let internal =
+            Internal::with_shared_branches(left_left, Arc::new(joined), node.left_metrics());
+        rotate(internal)
+    } else if right_height > left_height + 1 {
+        let Node::Internal(node) = right.as_ref() else {
+            unreachable!("Leaf cannot be taller than another node");
+        };
+        let [right_left, right_right] = node.branches.clone();
+        let joined = join(left, right_left, left_metrics);
+        let internal = Internal::with_shared_branches(
+            Arc::new(joined),
+            right_right,
+            left_metrics + node.left_metrics(),
+        );
+        rotate(internal)
+    } else {
+        if let (Node::Leaf(left), Node::Leaf(right)) = (left.as_ref(), right.as_ref()) {
+            if let Some(leaf) = Leaf::merge(left, right) {
+                return Node::from(leaf);
+            }
+        }
+        Node::from(Internal::with_shared_branches(left, right, left_metrics))
+    }
+}
+
+// Builds a balanced tree out of consecutive (node, metrics) parts.
+pub(crate) fn from_parts(parts: &[(Arc<Node>, Metrics)]) -> (Arc<Node>, Metrics) {
+    match parts {
+        [] => (Arc::new(Node::from("")), Metrics::default()),
+        [part] => part.clone(),
+        _ => {
+            let (left, left_metrics) = from_parts(&parts[..parts.len() / 2]);
+            let (right, right_metrics) = from_parts(&parts[parts.len() / 2..]);
+            let node = join(left, right, left_metrics);
+            (Arc::new(node), left_metrics + right_metrics)
+        }
+    }
+}
+
+// Restores balance of a node whose branches differ in height by at most two.
+fn rotate(mut node: Internal) -> Node {
+    let balance = node.balance_factor();
+
+    if balance > 1 {
+        if branch_balance_factor(&node.branches[0]) < 0 {
+            let left = into_internal(node.branches[0].as_ref().clone());
+            node.branches[0] = Arc::new(rotate_left(left));
+        }
+        rotate_right(node)
+    } else if balance < -1 {
+        if branch_balance_factor(&node.branches[1]) > 0 {
+            let right = into_internal(node.branches[1].as_ref().clone());
+            node.branches[1] = Arc::new(rotate_right(right));
+        }
+        rotate_left(node)
+    } else {
+        Node::from(node)
+    }
+}
+
+// (a, (b, c)) -> ((a, b), c)
+fn rotate_left(node: Internal) -> Node {
+    let a_metrics = node.left_metrics();
+    let [a, right] = node.branches;
+    let right = into_internal(Arc::unwrap_or_clone(right));
+    let b_metrics = right.left_metrics();
+    let [b, c] = right.branches;
+
+    let new_left = Internal::with_shared_branches(a, b, a_metrics);
+    Node::from(Internal::with_shared_branches(
+        Arc::new(Node::from(new_left)),
+        c,
+        a_metrics + b_metrics,
+    ))
+}
+
+// ((a, b), c) -> (a, (b, c))
+fn rotate_right(node: Internal) -> Node {
+    let ab_metrics = node.left_metrics();
+    let [left, c] = node.branches;
+    let left = into_internal(Arc::unwrap_or_clone(left));
+    let a_metrics = left.left_metrics();
+    let [a, b] = left.branches;
+
+    let new_right = Internal::with_shared_branches(b, c, ab_metrics - a_metrics);
+    Node::from(Internal::with_shared_branches(
+        a,
+        Arc::new(Node::from(new_right)),
+        a_metrics,
+    ))
+}
+
+fn branch_balance_factor(node: &Node) -> isize {
+    match node {
+        Node::Leaf(_) => 0,
+        Node::Internal(internal) => internal.balance_factor(),
+    }
+}
+
+fn into_internal(node: Node) -> Internal {
+    match node {
+        Node::Internal(internal) => internal,
+        Node::Leaf(_) => unreachable!("Rotated node has to be internal"),
+    }
+}
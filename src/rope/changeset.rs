@@ -0,0 +1,536 @@
+use std::mem;
+
+use super::{
+    error::{RopeError, RopeResult},
+    rope::Rope,
+};
+
+// Single step of a change set, walking the old text from its beginning. Lengths are in chars.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Operation {
+    // Keeps the next chars as they are.
+    Retain(usize),
+    // Removes the next chars.
+    Delete(usize),
+    // Adds text at the current position.
+    Insert(String),
+}
+
+// Which side of text inserted exactly at a position the position ends up on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Assoc {
+    Before,
+    After,
+}
+
+// Edit of a whole text, recorded as operations walking it from the beginning. Chars past the
+// last operation are retained. Change sets can be applied to a rope, inverted to undo them,
+// composed into one and used to map positions (cursors, selections) through the edit.
+//
+// Operations are kept normalized: neighbours of the same kind are merged, and an insert next to
+// a delete always comes first. Two change sets doing the same edit are then equal.
+#[derive(Clone, Debug, Default)]
+pub struct ChangeSet {
+    ops: Vec<Operation>,
+    // Length of the text before and after the edit.
+    len: usize,
+    len_after: usize,
+    // Chars of the old text walked by the operations so far.
+    covered: usize,
+}
+
+impl ChangeSet {
+    // Change set which leaves a text of given length as it is. Operations are added with
+    // `retain`, `delete` and `insert`.
+    pub fn new(len: usize) -> Self {
+        Self {
+            ops: vec![],
+            len,
+            len_after: len,
+            covered: 0,
+        }
+    }
+
+    // Inserts text at given char index.
+    pub fn insertion(len: usize, index: usize, text: &str) -> Self {
+        Self::try_insertion(len, index, text).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn try_insertion(len: usize, index: usize, text: &str) -> RopeResult<Self> {
+        Ok(Self::new(len).try_retain(index)?.insert(text))
+    }
+
+    // Deletes chars in given range.
+    pub fn deletion(len: usize, start: usize, end: usize) -> Self {
+        Self::try_deletion(len, start, end).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn try_deletion(len: usize, start: usize, end: usize) -> RopeResult<Self> {
+        if start > end {
+            return Err(RopeError::InvalidRange { start, end });
+        }
+        Self::new(len).try_retain(start)?.try_delete(end - start)
+    }
+
+    pub fn retain(self, count: usize) -> Self {
+        self.try_retain(count)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn try_retain(mut self, count: usize) -> RopeResult<Self> {
+        self.advance(count)?;
+        self.push(Operation::Retain(count));
+        Ok(self)
+    }
+
+    pub fn delete(self, count: usize) -> Self {
+        self.try_delete(count)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn try_delete(mut self, count: usize) -> RopeResult<Self> {
+        self.advance(count)?;
+        self.len_after -= count;
+        self.push(Operation::Delete(count));
+        Ok(self)
+    }
+
+    pub fn insert(mut self, text: &str) -> Self {
+        self.len_after += text.chars().count();
+        self.push(Operation::Insert(text.to_string()));
+        self
+    }
+
+    // Walks `count` more chars of the old text, failing if that goes past its end.
+    fn advance(&mut self, count: usize) -> RopeResult<()> {
+        let covered = self.covered + count;
+        if covered > self.len {
+            return Err(RopeError::IndexOutOfBounds {
+                index: covered,
+                len: self.len,
+            });
+        }
+        self.covered = covered;
+        Ok(())
+    }
+
+    fn push(&mut self, op: Operation) {
+        match (self.ops.last_mut(), op) {
+            (_, Operation::Retain(0) | Operation::Delete(0)) => {}
+            (_, Operation::Insert(text)) if text.is_empty() => {}
+            (Some(Operation::Retain(last)), Operation::Retain(count)) => *last += count,
+            (Some(Operation::Delete(last)), Operation::Delete(count)) => *last += count,
+            (Some(Operation::Insert(last)), Operation::Insert(text)) => last.push_str(&text),
+            (Some(Operation::Delete(_)), Operation::Insert(text)) => {
+                // Insert goes before the delete, merged with an insert preceding it, if any.
+                let delete = self.ops.pop().expect("Last operation is a delete");
+                self.push(Operation::Insert(text));
+                self.ops.push(delete);
+            }
+            (_, op) => self.ops.push(op),
+        }
+    }
+
+    // Operations including retaining the rest of the text.
+    pub fn ops(&self) -> Vec<Operation> {
+        let rest = self.len - self.covered;
+        let mut ops = self.ops.clone();
+        match ops.last_mut() {
+            _ if rest == 0 => {}
+            Some(Operation::Retain(count)) => *count += rest,
+            _ => ops.push(Operation::Retain(rest)),
+        }
+        ops
+    }
+
+    pub fn len_before(&self) -> usize {
+        self.len
+    }
+
+    pub fn len_after(&self) -> usize {
+        self.len_after
+    }
+
+    // True if applying it changes nothing.
+    pub fn is_empty(&self) -> bool {
+        self.ops.iter().all(|op| matches!(op, Operation::Retain(_)))
+    }
+
+    pub fn apply(&self, rope: &mut Rope) {
+        self.try_apply(rope).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn try_apply(&self, rope: &mut Rope) -> RopeResult<()> {
+        if rope.len() != self.len {
+            return Err(RopeError::LengthMismatch {
+                expected: self.len,
+                found: rope.len(),
+            });
+        }
+
+        let mut position = 0;
+        for op in &self.ops {
+            match op {
+                Operation::Retain(count) => position += count,
+                Operation::Delete(count) => rope.try_remove_range(position..position + count)?,
+                Operation::Insert(text) => {
+                    rope.try_insert(position, text)?;
+                    position += text.chars().count();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Change set undoing this one. `original` is the text this one applies to.
+    pub fn invert(&self, original: &Rope) -> ChangeSet {
+        self.try_invert(original)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn try_invert(&self, original: &Rope) -> RopeResult<ChangeSet> {
+        if original.len() != self.len {
+            return Err(RopeError::LengthMismatch {
+                expected: self.len,
+                found: original.len(),
+            });
+        }
+
+        let mut inverted = ChangeSet::new(self.len_after);
+        let mut position = 0;
+        for op in &self.ops {
+            inverted = match op {
+                Operation::Retain(count) => {
+                    position += count;
+                    inverted.retain(*count)
+                }
+                Operation::Delete(count) => {
+                    let removed = original.slice(position..position + count).to_string();
+                    position += count;
+                    inverted.insert(&removed)
+                }
+                Operation::Insert(text) => inverted.delete(text.chars().count()),
+            };
+        }
+        Ok(inverted)
+    }
+
+    // Single change set doing what this one followed by `next` does.
+    pub fn compose(&self, next: &ChangeSet) -> ChangeSet {
+        self.try_compose(next)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn try_compose(&self, next: &ChangeSet) -> RopeResult<ChangeSet> {
+        if next.len != self.len_after {
+            return Err(RopeError::LengthMismatch {
+                expected: self.len_after,
+                found: next.len,
+            });
+        }
+
+        let mut composed = ChangeSet::new(self.len);
+        let mut first = self.ops().into_iter();
+        let mut second = next.ops().into_iter();
+        let (mut a, mut b) = (first.next(), second.next());
+
+        // Walks the text in between both edits. Deletes of the first one and inserts of the
+        // second one never touch it, everything else is split to consume it in equal steps.
+        loop {
+            match (a.take(), b.take()) {
+                (None, None) => break,
+                (Some(Operation::Delete(count)), rest) => {
+                    composed = composed.delete(count);
+                    (a, b) = (first.next(), rest);
+                }
+                (rest, Some(Operation::Insert(text))) => {
+                    composed = composed.insert(&text);
+                    (a, b) = (rest, second.next());
+                }
+                (Some(Operation::Retain(x)), Some(Operation::Retain(y))) => {
+                    let step = x.min(y);
+                    composed = composed.retain(step);
+                    a = shrink(Operation::Retain(x), step).or_else(|| first.next());
+                    b = shrink(Operation::Retain(y), step).or_else(|| second.next());
+                }
+                (Some(Operation::Retain(x)), Some(Operation::Delete(y))) => {
+                    let step = x.min(y);
+                    composed = composed.delete(step);
+                    a = shrink(Operation::Retain(x), step).or_else(|| first.next());
+                    b = shrink(Operation::Delete(y), step).or_else(|| second.next());
+                }
+                (Some(Operation::Insert(text)), Some(op)) => {
+                    let step = text.chars().count().min(op_len(&op));
+                    if let Operation::Retain(_) = op {
+                        let kept: String = text.chars().take(step).collect();
+                        composed = composed.insert(&kept);
+                    }
+                    a = shrink(Operation::Insert(text), step).or_else(|| first.next());
+                    b = shrink(op, step).or_else(|| second.next());
+                }
+                _ => unreachable!("Both change sets walk the same text"),
+            }
+        }
+        Ok(composed)
+    }
+
+    // Position in the edited text corresponding to a char index of the old one. Positions
+    // inside deleted text move to where it was.
+    pub fn map_pos(&self, pos: usize, assoc: Assoc) -> usize {
+        self.try_map_pos(pos, assoc)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn try_map_pos(&self, pos: usize, assoc: Assoc) -> RopeResult<usize> {
+        if pos > self.len {
+            return Err(RopeError::IndexOutOfBounds {
+                index: pos,
+                len: self.len,
+            });
+        }
+
+        let mut old = 0;
+        let mut new = 0;
+        for op in &self.ops {
+            match op {
+                Operation::Retain(count) => {
+                    if pos < old + count {
+                        return Ok(new + pos - old);
+                    }
+                    old += count;
+                    new += count;
+                }
+                Operation::Delete(count) => {
+                    if pos < old + count {
+                        return Ok(new);
+                    }
+                    old += count;
+                }
+                Operation::Insert(text) => {
+                    if pos == old && assoc == Assoc::Before {
+                        return Ok(new);
+                    }
+                    new += text.chars().count();
+                }
+            }
+        }
+        Ok(new + pos - old)
+    }
+}
+
+// Retaining the rest explicitly or not is the same edit.
+impl PartialEq for ChangeSet {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.ops() == other.ops()
+    }
+}
+
+impl Eq for ChangeSet {}
+
+fn op_len(op: &Operation) -> usize {
+    match op {
+        Operation::Retain(count) | Operation::Delete(count) => *count,
+        Operation::Insert(text) => text.chars().count(),
+    }
+}
+
+// What's left of the operation after `step` chars of it. None if nothing.
+fn shrink(op: Operation, step: usize) -> Option<Operation> {
+    let rest = match op {
+        Operation::Retain(count) => Operation::Retain(count - step),
+        Operation::Delete(count) => Operation::Delete(count - step),
+        Operation::Insert(mut text) => {
+            let split = text
+                .char_indices()
+                .nth(step)
+                .map_or(text.len(), |(idx, _)| idx);
+            Operation::Insert(mem::take(&mut text).split_off(split))
+        }
+    };
+    (op_len(&rest) > 0).then_some(rest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Assoc, ChangeSet, Operation};
+    use crate::rope::{error::RopeError, rope::Rope};
+
+    #[test]
+    fn apply_operations() {
+        let mut rope = Rope::from("Hello World");
+        let changes = ChangeSet::new(11)
+            .retain(6)
+            .insert("beautiful ")
+            .delete(5)
+            .insert("rope");
+
+        changes.apply(&mut rope);
+
+        assert_eq!("Hello beautiful rope", rope.value());
+        assert_eq!(20, changes.len_after());
+    }
+
+    #[test]
+    fn operations_are_normalized() {
+        let changes = ChangeSet::new(10)
+            .retain(1)
+            .retain(2)
+            .delete(2)
+            .insert("ab")
+            .delete(1)
+            .insert("c")
+            .retain(0);
+
+        assert_eq!(
+            vec![
+                Operation::Retain(3),
+                Operation::Insert(String::from("abc")),
+                Operation::Delete(3),
+                Operation::Retain(4),
+            ],
+            changes.ops()
+        );
+        assert_eq!(ChangeSet::new(5).retain(2), ChangeSet::new(5));
+        assert!(ChangeSet::new(5).retain(5).is_empty());
+        assert!(!ChangeSet::insertion(5, 5, "!").is_empty());
+    }
+
+    #[test]
+    fn invert_undoes_changes() {
+        let original = Rope::from("zażółć gęślą jaźń");
+        let changes = ChangeSet::new(17)
+            .delete(2)
+            .retain(5)
+            .insert("żółte ")
+            .delete(6);
+
+        let mut rope = original.clone();
+        changes.apply(&mut rope);
+        assert_eq!("żółć żółte jaźń", rope.value());
+
+        changes.invert(&original).apply(&mut rope);
+        assert_eq!(original.value(), rope.value());
+    }
+
+    #[test]
+    fn compose_matches_applying_in_turn() {
+        let original = Rope::from("one two three");
+        let first = ChangeSet::new(13).retain(4).delete(4).insert("2 ");
+        let second = ChangeSet::new(11)
+            .insert(">> ")
+            .retain(2)
+            .delete(3)
+            .insert("E");
+
+        let mut in_turn = original.clone();
+        first.apply(&mut in_turn);
+        second.apply(&mut in_turn);
+
+        let composed = first.compose(&second);
+        let mut at_once = original.clone();
+        composed.apply(&mut at_once);
+
+        assert_eq!(">> onE three", in_turn.value());
+        assert_eq!(in_turn.value(), at_once.value());
+        assert_eq!(13, composed.len_before());
+        assert_eq!(12, composed.len_after());
+    }
+
+    #[test]
+    fn compose_deletes_inserted_text() {
+        let first = ChangeSet::insertion(3, 1, "xyz");
+        let second = ChangeSet::deletion(6, 2, 5);
+
+        let composed = first.compose(&second);
+
+        let mut rope = Rope::from("abc");
+        composed.apply(&mut rope);
+        assert_eq!("axc", rope.value());
+        assert_eq!(
+            vec![
+                Operation::Retain(1),
+                Operation::Insert(String::from("x")),
+                Operation::Delete(1),
+                Operation::Retain(1),
+            ],
+            composed.ops()
+        );
+    }
+
+    #[test]
+    fn map_positions_through_edit() {
+        // "Hello World" -> "Hi World!"
+        let changes = ChangeSet::new(11)
+            .retain(1)
+            .delete(4)
+            .insert("i")
+            .retain(6)
+            .insert("!");
+
+        assert_eq!(0, changes.map_pos(0, Assoc::Before));
+        assert_eq!(1, changes.map_pos(1, Assoc::Before));
+        assert_eq!(2, changes.map_pos(1, Assoc::After));
+        assert_eq!(2, changes.map_pos(3, Assoc::After));
+        assert_eq!(4, changes.map_pos(7, Assoc::Before));
+        assert_eq!(8, changes.map_pos(11, Assoc::Before));
+        assert_eq!(9, changes.map_pos(11, Assoc::After));
+    }
+
+    #[test]
+    fn lengths_have_to_match() {
+        let changes = ChangeSet::insertion(3, 0, "a");
+        let mut rope = Rope::from("ab");
+
+        assert_eq!(
+            Err(RopeError::LengthMismatch {
+                expected: 3,
+                found: 2
+            }),
+            changes.try_apply(&mut rope)
+        );
+        assert_eq!(
+            Err(RopeError::LengthMismatch {
+                expected: 4,
+                found: 3
+            }),
+            changes.try_compose(&changes)
+        );
+        assert_eq!("ab", rope.value());
+    }
+
+    #[test]
+    #[should_panic]
+    fn walking_past_the_end_panics() {
+        let _ = ChangeSet::new(3).retain(2).delete(2);
+    }
+
+    #[test]
+    fn invalid_steps_are_errors() {
+        assert_eq!(
+            Err(RopeError::IndexOutOfBounds { index: 4, len: 3 }),
+            ChangeSet::new(3).retain(2).try_delete(2)
+        );
+        assert_eq!(
+            Err(RopeError::InvalidRange { start: 3, end: 1 }),
+            ChangeSet::try_deletion(5, 3, 1)
+        );
+        assert_eq!(
+            Err(RopeError::IndexOutOfBounds { index: 6, len: 5 }),
+            ChangeSet::try_insertion(5, 6, "x")
+        );
+        assert_eq!(
+            Err(RopeError::IndexOutOfBounds { index: 6, len: 5 }),
+            ChangeSet::deletion(5, 1, 2).try_map_pos(6, Assoc::After)
+        );
+        assert_eq!(
+            Ok(4),
+            ChangeSet::deletion(5, 1, 2).try_map_pos(5, Assoc::After)
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn deletion_with_reversed_range_panics() {
+        let _ = ChangeSet::deletion(5, 3, 1);
+    }
+}
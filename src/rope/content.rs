@@ -0,0 +1,119 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io,
+    ops::{Deref, Range},
+    path::Path,
+    sync::Arc,
+};
+
+use memmap2::Mmap;
+
+// Bytes of a leaf. Leaves of a rope opened from a file point into a memory map of its snapshot, so
+// the OS pages content in when it's read and may drop it again under memory pressure. A mapped leaf is copied
+// into its own buffer the first time it's edited, other leaves keep sharing the map.
+#[derive(Clone, Debug)]
+pub(crate) enum Content {
+    Owned(Vec<u8>),
+    Mapped(Arc<Mmap>, Range<usize>),
+}
+
+impl Content {
+    // Buffer to edit in place, copying mapped bytes out first.
+    pub(crate) fn to_mut(&mut self) -> &mut Vec<u8> {
+        if let Content::Mapped(map, range) = self {
+            *self = Content::Owned(map[range.clone()].to_vec());
+        }
+        match self {
+            Content::Owned(val) => val,
+            Content::Mapped(..) => unreachable!("Content was just copied out"),
+        }
+    }
+
+    // Mapped bytes live in the page cache, not on the heap.
+    pub(crate) fn heap_size(&self) -> usize {
+        match self {
+            Content::Owned(val) => val.capacity(),
+            Content::Mapped(..) => 0,
+        }
+    }
+
+    pub(crate) fn is_mapped(&self) -> bool {
+        matches!(self, Content::Mapped(..))
+    }
+}
+
+impl Default for Content {
+    fn default() -> Self {
+        Content::Owned(vec![])
+    }
+}
+
+impl Deref for Content {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Content::Owned(val) => val,
+            Content::Mapped(map, range) => &map[range.clone()],
+        }
+    }
+}
+
+// Maps a snapshot of the whole file read-only. Returns None for an empty file, which cannot be
+// mapped. Mapping the file itself would leave the rope at the mercy of other processes: a file
+// rewritten in place changes under the rope, and one truncated while it's mapped (e.g. a rotated
+// log) kills the process with SIGBUS on the next read. So the content is copied to a file nobody
+// else can open first. The copy takes disk space rather than memory, and on filesystems which
+// support it shares the blocks of the original instead of copying them.
+pub(crate) fn map_file(path: &Path) -> io::Result<Option<Arc<Mmap>>> {
+    let mut file = File::open(path)?;
+    if file.metadata()?.len() == 0 {
+        return Ok(None);
+    }
+    let mut snapshot = create_snapshot(path)?;
+    io::copy(&mut file, &mut snapshot)?;
+    if snapshot.metadata()?.len() == 0 {
+        return Ok(None);
+    }
+    // Safety: the snapshot has no name left, so only this process can change it, and it only
+    // ever reads the map through shared slices.
+    let map = unsafe { Mmap::map(&snapshot)? };
+    Ok(Some(Arc::new(map)))
+}
+
+// Snapshot goes next to the file, so that it's on the same filesystem, or to the temp directory
+// if that one isn't writable.
+fn create_snapshot(path: &Path) -> io::Result<File> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let name = format!(".{}.{}.snapshot", name, std::process::id());
+    let dir = path.parent().unwrap_or(Path::new(""));
+    create_unnamed(&dir.join(&name)).or_else(|_| create_unnamed(&std::env::temp_dir().join(&name)))
+}
+
+// Creates a new file and removes its name right away, it's deleted once the map is dropped.
+#[cfg(not(windows))]
+fn create_unnamed(path: &Path) -> io::Result<File> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(path)?;
+    fs::remove_file(path)?;
+    Ok(file)
+}
+
+// An open file cannot be removed on Windows, instead it's deleted once the last handle to it is
+// closed, and no one else is allowed to open it in the meantime.
+#[cfg(windows)]
+fn create_unnamed(path: &Path) -> io::Result<File> {
+    use std::os::windows::fs::OpenOptionsExt;
+
+    const FILE_FLAG_DELETE_ON_CLOSE: u32 = 0x0400_0000;
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .share_mode(0)
+        .custom_flags(FILE_FLAG_DELETE_ON_CLOSE)
+        .open(path)
+}
@@ -0,0 +1,220 @@
+use std::{collections::HashSet, fmt::Write, sync::Arc};
+
+use super::{
+    error::{InvariantError, Violation},
+    leaf::{Leaf, MAX_LEAF_LEN, MAX_MAPPED_LEAF_LEN},
+    metrics::Metrics,
+    node::{Node, Weight},
+};
+
+// Number of chars of leaf content shown in dumps.
+const PREVIEW_LEN: usize = 16;
+
+// Mapped leaves are allowed to cover more of the file than owned ones hold.
+fn max_len(leaf: &Leaf) -> usize {
+    if leaf.is_mapped() {
+        MAX_MAPPED_LEAF_LEN
+    } else {
+        MAX_LEAF_LEN
+    }
+}
+
+// Walks the whole subtree, checking every node against what's actually below it.
+// `path` holds branches taken from the root. Returns metrics and height of the subtree.
+pub(crate) fn check_node(
+    node: &Node,
+    path: &mut String,
+) -> Result<(Metrics, usize), InvariantError> {
+    let fail = |path: &String, violation| {
+        Err(InvariantError {
+            path: path.clone(),
+            violation,
+        })
+    };
+
+    match node {
+        Node::Leaf(leaf) => {
+            let bytes = leaf.get_char_bytes();
+            if bytes.len() > max_len(leaf) {
+                return fail(path, Violation::LeafTooLong { len: bytes.len() });
+            }
+            if bytes.is_empty() && !path.is_empty() {
+                return fail(path, Violation::EmptyLeaf);
+            }
+            let text = match std::str::from_utf8(bytes) {
+                Ok(text) => text,
+                Err(err) => {
+                    let offset = err.valid_up_to();
+                    return fail(path, Violation::InvalidUtf8 { offset });
+                }
+            };
+            let chars = text.chars().count();
+            if chars != leaf.weight() {
+                return fail(
+                    path,
+                    Violation::WrongCharCount {
+                        expected: chars,
+                        found: leaf.weight(),
+                    },
+                );
+            }
+            Ok((Metrics::of(bytes), 0))
+        }
+        Node::Internal(internal) => {
+            path.push('L');
+            let (left, left_height) = check_node(&internal.branches[0], path)?;
+            path.pop();
+            path.push('R');
+            let (right, right_height) = check_node(&internal.branches[1], path)?;
+            path.pop();
+
+            let height = 1 + left_height.max(right_height);
+            if internal.weight != left.chars {
+                return fail(
+                    path,
+                    Violation::WrongWeight {
+                        expected: left.chars,
+                        found: internal.weight,
+                    },
+                );
+            }
+            if internal.line_weight != left.newlines {
+                return fail(
+                    path,
+                    Violation::WrongLineWeight {
+                        expected: left.newlines,
+                        found: internal.line_weight,
+                    },
+                );
+            }
+            if internal.byte_weight != left.bytes {
+                return fail(
+                    path,
+                    Violation::WrongByteWeight {
+                        expected: left.bytes,
+                        found: internal.byte_weight,
+                    },
+                );
+            }
+            if internal.utf16_weight != left.utf16 {
+                return fail(
+                    path,
+                    Violation::WrongUtf16Weight {
+                        expected: left.utf16,
+                        found: internal.utf16_weight,
+                    },
+                );
+            }
+            if internal.height != height {
+                return fail(
+                    path,
+                    Violation::WrongHeight {
+                        expected: height,
+                        found: internal.height,
+                    },
+                );
+            }
+            if left_height.abs_diff(right_height) > 1 {
+                return fail(
+                    path,
+                    Violation::Unbalanced {
+                        left: left_height,
+                        right: right_height,
+                    },
+                );
+            }
+            Ok((left + right, height))
+        }
+    }
+}
+
+// One line per node, indented by depth. Every node shows how many times it's shared (`refs`),
+// leaves show how full they are and the beginning of their content.
+pub(crate) fn write_indented(out: &mut String, node: &Arc<Node>, depth: usize) {
+    let _ = writeln!(
+        out,
+        "{}{} depth={} refs={}",
+        "  ".repeat(depth),
+        describe(node),
+        depth,
+        Arc::strong_count(node)
+    );
+    if let Node::Internal(internal) = node.as_ref() {
+        write_indented(out, &internal.branches[0], depth + 1);
+        write_indented(out, &internal.branches[1], depth + 1);
+    }
+}
+
+// Graphviz (dot) rendering. Nodes are identified by address, so subtrees shared within the rope
+// are drawn only once, with an edge from each of their parents.
+pub(crate) fn write_graphviz(out: &mut String, root: &Arc<Node>) {
+    out.push_str("digraph rope {\n");
+    out.push_str("  node [shape=box, fontname=monospace];\n");
+    let mut seen = HashSet::new();
+    write_graphviz_node(out, root, 0, &mut seen);
+    out.push_str("}\n");
+}
+
+fn write_graphviz_node(
+    out: &mut String,
+    node: &Arc<Node>,
+    depth: usize,
+    seen: &mut HashSet<*const Node>,
+) {
+    let id = Arc::as_ptr(node);
+    if !seen.insert(id) {
+        return;
+    }
+
+    let label = format!(
+        "{}\ndepth={} refs={}",
+        describe(node),
+        depth,
+        Arc::strong_count(node)
+    );
+    let _ = writeln!(out, "  \"{:p}\" [label={:?}];", id, label);
+
+    if let Node::Internal(internal) = node.as_ref() {
+        for (branch, side) in internal.branches.iter().zip(["L", "R"]) {
+            let _ = writeln!(
+                out,
+                "  \"{:p}\" -> \"{:p}\" [label=\"{}\"];",
+                id,
+                Arc::as_ptr(branch),
+                side
+            );
+            write_graphviz_node(out, branch, depth + 1, seen);
+        }
+    }
+}
+
+fn describe(node: &Node) -> String {
+    match node {
+        Node::Leaf(leaf) => {
+            let bytes = leaf.get_char_bytes().len();
+            let max = max_len(leaf);
+            // Lossy, so that even a broken leaf can be shown.
+            let content = String::from_utf8_lossy(leaf.get_char_bytes());
+            let preview: String = content.chars().take(PREVIEW_LEN).collect();
+            let ellipsis = if leaf.weight() > PREVIEW_LEN {
+                "…"
+            } else {
+                ""
+            };
+            format!(
+                "Leaf {}/{} bytes ({}%) chars={} lines={} {:?}{}",
+                bytes,
+                max,
+                bytes * 100 / max,
+                leaf.weight(),
+                leaf.newlines(),
+                preview,
+                ellipsis
+            )
+        }
+        Node::Internal(internal) => format!(
+            "Internal height={} weight={} line_weight={}",
+            internal.height, internal.weight, internal.line_weight
+        ),
+    }
+}
@@ -0,0 +1,555 @@
+use std::{fmt, ops::Range, sync::Arc};
+
+use super::{changeset::ChangeSet, node::Node, rope::Rope};
+
+// Diffing works in two steps. Common beginning and end of both ropes are skipped first, walking
+// both trees side by side: subtrees shared by the two (pointer-equal) are skipped whole without
+// looking at their content, only the leaves that differ are compared char by char. What's left
+// in between is extended to whole lines and diffed line by line with Myers' algorithm.
+
+// Above this many differing lines, the whole region is replaced instead, as Myers' algorithm
+// keeps O(d^2) state for `d` differences.
+const MAX_EDIT_DISTANCE: usize = 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Edit {
+    Equal,
+    Delete,
+    Insert,
+}
+
+// Line of a unified diff hunk, including its line break if it has one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DiffLine {
+    Context(String),
+    Removed(String),
+    Added(String),
+}
+
+// Group of nearby changes with the unchanged lines around them, as in unified diff format.
+// Line numbers are 0-based, `Display` prints them 1-based.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Hunk {
+    pub old_start: usize,
+    pub old_len: usize,
+    pub new_start: usize,
+    pub new_len: usize,
+    pub lines: Vec<DiffLine>,
+}
+
+// Lines which differ between two ropes. Both ranges start at the same line, as everything
+// before it is identical.
+struct LineDiff {
+    old: Range<usize>,
+    new: Range<usize>,
+    // Char index of the first differing line, the same in both ropes.
+    start: usize,
+    old_lines: Vec<String>,
+    new_lines: Vec<String>,
+    edits: Vec<Edit>,
+}
+
+pub(crate) fn diff(old: &Rope, new: &Rope) -> ChangeSet {
+    let Some(diff) = line_diff(old, new) else {
+        return ChangeSet::new(old.len());
+    };
+
+    let mut changes = ChangeSet::new(old.len()).retain(diff.start);
+    let (mut old_lines, mut new_lines) = (diff.old_lines.iter(), diff.new_lines.iter());
+    for edit in diff.edits {
+        changes = match edit {
+            Edit::Equal => {
+                new_lines.next();
+                changes.retain(next_line(&mut old_lines).chars().count())
+            }
+            Edit::Delete => changes.delete(next_line(&mut old_lines).chars().count()),
+            Edit::Insert => changes.insert(next_line(&mut new_lines)),
+        };
+    }
+    changes
+}
+
+pub(crate) fn hunks(old: &Rope, new: &Rope, context: usize) -> Vec<Hunk> {
+    let Some(diff) = line_diff(old, new) else {
+        return vec![];
+    };
+
+    // Runs of changed lines, as (old lines, new lines) ranges.
+    let mut blocks: Vec<(Range<usize>, Range<usize>)> = vec![];
+    let (mut old_line, mut new_line) = (diff.old.start, diff.new.start);
+    for edit in diff.edits {
+        if edit != Edit::Equal {
+            match blocks.last_mut() {
+                Some((old, new)) if old.end == old_line && new.end == new_line => {}
+                _ => blocks.push((old_line..old_line, new_line..new_line)),
+            }
+        }
+        let (old, new) = blocks.last_mut().expect("Changes start with a block");
+        match edit {
+            Edit::Equal => {
+                old_line += 1;
+                new_line += 1;
+            }
+            Edit::Delete => {
+                old_line += 1;
+                old.end = old_line;
+            }
+            Edit::Insert => {
+                new_line += 1;
+                new.end = new_line;
+            }
+        }
+    }
+
+    let old_total = line_total(old);
+    let mut hunks: Vec<Hunk> = vec![];
+    let mut group: Vec<(Range<usize>, Range<usize>)> = vec![];
+    for block in blocks {
+        if let Some((last, _)) = group.last() {
+            if block.0.start - last.end > 2 * context {
+                hunks.push(hunk(old, new, &group, context, old_total));
+                group.clear();
+            }
+        }
+        group.push(block);
+    }
+    if !group.is_empty() {
+        hunks.push(hunk(old, new, &group, context, old_total));
+    }
+    hunks
+}
+
+fn hunk(
+    old: &Rope,
+    new: &Rope,
+    blocks: &[(Range<usize>, Range<usize>)],
+    context: usize,
+    old_total: usize,
+) -> Hunk {
+    let (first_old, first_new) = &blocks[0];
+    let old_start = first_old.start.saturating_sub(context);
+    let new_start = first_new.start - (first_old.start - old_start);
+
+    let mut lines = vec![];
+    let mut position = old_start;
+    for (old_lines, new_lines) in blocks {
+        lines.extend((position..old_lines.start).map(|idx| DiffLine::Context(line(old, idx))));
+        lines.extend(
+            old_lines
+                .clone()
+                .map(|idx| DiffLine::Removed(line(old, idx))),
+        );
+        lines.extend(new_lines.clone().map(|idx| DiffLine::Added(line(new, idx))));
+        position = old_lines.end;
+    }
+    let old_end = (position + context).min(old_total);
+    lines.extend((position..old_end).map(|idx| DiffLine::Context(line(old, idx))));
+
+    let old_len = old_end - old_start;
+    let added: usize = blocks.iter().map(|(_, new)| new.len()).sum();
+    let removed: usize = blocks.iter().map(|(old, _)| old.len()).sum();
+    Hunk {
+        old_start,
+        old_len,
+        new_start,
+        new_len: old_len + added - removed,
+        lines,
+    }
+}
+
+// Finds the lines in between the common beginning and end, and diffs them.
+fn line_diff(old: &Rope, new: &Rope) -> Option<LineDiff> {
+    let prefix = common_prefix(old, new);
+    let max_suffix = old.len().min(new.len()) - prefix;
+    let suffix = common_suffix(old, new, max_suffix);
+    if prefix == old.len() && prefix == new.len() {
+        return None;
+    }
+
+    // Both ropes are the same up to `prefix`, so the line it falls in starts at the same char in
+    // both. The common suffix is cut to start right after its first line break, which makes it
+    // start a line in both ropes, whatever precedes it. Without one it's not kept at all.
+    let first_line = old.char_to_line(prefix);
+    let start = old.line_to_char(first_line);
+    let suffix = match old
+        .slice(old.len() - suffix..)
+        .chars()
+        .position(|c| c == '\n')
+    {
+        Some(newline) => suffix - newline - 1,
+        None => 0,
+    };
+
+    let old_lines = lines(old, start..old.len() - suffix);
+    let new_lines = lines(new, start..new.len() - suffix);
+    let edits = myers(&old_lines, &new_lines).unwrap_or_else(|| {
+        let mut edits = vec![Edit::Delete; old_lines.len()];
+        edits.extend(vec![Edit::Insert; new_lines.len()]);
+        edits
+    });
+
+    Some(LineDiff {
+        old: first_line..first_line + old_lines.len(),
+        new: first_line..first_line + new_lines.len(),
+        start,
+        old_lines,
+        new_lines,
+        edits,
+    })
+}
+
+// Number of chars both ropes start with.
+pub(crate) fn common_prefix(old: &Rope, new: &Rope) -> usize {
+    let skipped = skip_shared(&old.root, &new.root, 0);
+    let matching = old
+        .chars_at(skipped)
+        .zip(new.chars_at(skipped))
+        .take_while(|(a, b)| a == b)
+        .count();
+    skipped + matching
+}
+
+// Number of chars both ropes end with, at most `max`.
+pub(crate) fn common_suffix(old: &Rope, new: &Rope, max: usize) -> usize {
+    let skipped = skip_shared(&old.root, &new.root, 1).min(max);
+    let old_rest = old.slice(..old.len() - skipped);
+    let new_rest = new.slice(..new.len() - skipped);
+    let matching = old_rest
+        .chars()
+        .rev()
+        .zip(new_rest.chars().rev())
+        .take(max - skipped)
+        .take_while(|(a, b)| a == b)
+        .count();
+    skipped + matching
+}
+
+// Walks both trees from one side (0 for the beginning, 1 for the end), skipping subtrees they
+// share, until the first pair of differing leaves. Returns the number of chars skipped.
+pub(crate) fn skip_shared(old: &Arc<Node>, new: &Arc<Node>, side: usize) -> usize {
+    let (mut old_stack, mut new_stack) = (vec![old.clone()], vec![new.clone()]);
+    let mut skipped = 0;
+
+    while let (Some(a), Some(b)) = (old_stack.last(), new_stack.last()) {
+        if Arc::ptr_eq(a, b) {
+            skipped += a.len();
+            old_stack.pop();
+            new_stack.pop();
+            continue;
+        }
+
+        // Descends the taller one, or both, so that shared subtrees at the same depth meet.
+        let (a_height, b_height) = (a.height(), b.height());
+        if a_height == 0 && b_height == 0 {
+            break;
+        }
+        if a_height >= b_height {
+            expand(&mut old_stack, side);
+        }
+        if b_height >= a_height {
+            expand(&mut new_stack, side);
+        }
+    }
+    skipped
+}
+
+// Replaces the top of the stack with its branches, the one at `side` on top.
+fn expand(stack: &mut Vec<Arc<Node>>, side: usize) {
+    let node = stack.pop().expect("Stack is not empty");
+    let Node::Internal(internal) = node.as_ref() else {
+        unreachable!("Only internal nodes are expanded");
+    };
+    stack.push(internal.branches[1 - side].clone());
+    stack.push(internal.branches[side].clone());
+}
+
+fn lines(rope: &Rope, range: Range<usize>) -> Vec<String> {
+    rope.slice(range)
+        .to_string()
+        .split_inclusive('\n')
+        .map(String::from)
+        .collect()
+}
+
+// Line at given index, with its line break.
+fn line(rope: &Rope, idx: usize) -> String {
+    rope.slice(rope.line_to_char(idx)..rope.line_to_char(idx + 1))
+        .to_string()
+}
+
+// Number of lines, not counting the empty one after a trailing line break.
+fn line_total(rope: &Rope) -> usize {
+    let lines = rope.line_count();
+    if rope.len() == 0 || rope.chars().next_back() == Some('\n') {
+        lines - 1
+    } else {
+        lines
+    }
+}
+
+fn next_line<'a>(lines: &mut impl Iterator<Item = &'a String>) -> &'a String {
+    lines.next().expect("Edit script matches both texts")
+}
+
+// Shortest edit script turning `a` into `b` (Myers, 1986). Keeps the furthest reaching path of
+// every diagonal for each number of differences, and walks them back from the end. Returns None
+// if there are more than MAX_EDIT_DISTANCE differences.
+fn myers(a: &[String], b: &[String]) -> Option<Vec<Edit>> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = (a.len() + b.len()).min(MAX_EDIT_DISTANCE) as isize;
+    let offset = max + 1;
+    let mut v = vec![0isize; 2 * offset as usize + 1];
+    // Diagonals -d-1..=d+1 of `v` before each step.
+    let mut trace: Vec<Vec<isize>> = vec![];
+
+    let mut found = None;
+    'search: for d in 0..=max {
+        trace.push(v[(offset - d - 1) as usize..=(offset + d + 1) as usize].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let idx = (offset + k) as usize;
+            let mut x = if k == -d || (k != d && v[idx - 1] < v[idx + 1]) {
+                v[idx + 1]
+            } else {
+                v[idx - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[idx] = x;
+            if x >= n && y >= m {
+                found = Some(d);
+                break 'search;
+            }
+        }
+    }
+
+    let mut edits = vec![];
+    let (mut x, mut y) = (n, m);
+    for d in (0..=found?).rev() {
+        let v = &trace[d as usize];
+        let at = |k: isize| v[(k + d + 1) as usize];
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && at(k - 1) < at(k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = at(prev_k);
+        let prev_y = prev_x - prev_k;
+
+        while x > prev_x && y > prev_y {
+            edits.push(Edit::Equal);
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            edits.push(if x == prev_x {
+                Edit::Insert
+            } else {
+                Edit::Delete
+            });
+        }
+        (x, y) = (prev_x, prev_y);
+    }
+    edits.reverse();
+    Some(edits)
+}
+
+impl fmt::Display for DiffLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (sign, text) = match self {
+            DiffLine::Context(text) => (' ', text),
+            DiffLine::Removed(text) => ('-', text),
+            DiffLine::Added(text) => ('+', text),
+        };
+        write!(f, "{}{}", sign, text)?;
+        if !text.ends_with('\n') {
+            write!(f, "\n\\ No newline at end of file\n")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Hunk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Empty ranges point at the line before them.
+        let start = |start: usize, len: usize| if len == 0 { start } else { start + 1 };
+        writeln!(
+            f,
+            "@@ -{},{} +{},{} @@",
+            start(self.old_start, self.old_len),
+            self.old_len,
+            start(self.new_start, self.new_len),
+            self.new_len
+        )?;
+        for line in &self.lines {
+            write!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{myers, skip_shared, DiffLine, Edit, Hunk};
+    use crate::rope::{leaf::MAX_LEAF_LEN, rope::Rope};
+
+    fn lines(text: &str) -> Vec<String> {
+        text.split_inclusive('\n').map(String::from).collect()
+    }
+
+    fn check_diff(old: &str, new: &str) {
+        let (old, new) = (Rope::from(old), Rope::from(new));
+        let changes = old.diff(&new);
+        let mut rope = old.clone();
+        changes.apply(&mut rope);
+        assert_eq!(new.value(), rope.value());
+    }
+
+    #[test]
+    fn shortest_edit_script() {
+        let (a, b) = (lines("a\nb\nc\na\nb\nb\na\n"), lines("c\nb\na\nb\na\nc\n"));
+
+        let edits = myers(&a, &b).unwrap();
+
+        let changes = edits.iter().filter(|e| **e != Edit::Equal).count();
+        assert_eq!(5, changes);
+        assert_eq!(
+            a.len(),
+            edits.iter().filter(|e| **e != Edit::Insert).count()
+        );
+        assert_eq!(
+            b.len(),
+            edits.iter().filter(|e| **e != Edit::Delete).count()
+        );
+    }
+
+    #[test]
+    fn diff_applies_to_old_rope() {
+        check_diff("", "");
+        check_diff("", "new\ntext");
+        check_diff("old\ntext\n", "");
+        check_diff("one\ntwo\nthree\n", "one\n2\nthree\n");
+        check_diff("one\ntwo\nthree", "zero\none\ntwo\nthree\nfour");
+        check_diff("zażółć\ngęślą\njaźń", "zażółć\ngęś\njaźń");
+        check_diff("abc", "abd");
+        check_diff("a\nb\nc\na\nb\nb\na\n", "c\nb\na\nb\na\nc\n");
+    }
+
+    #[test]
+    fn diff_keeps_unchanged_lines() {
+        let old = Rope::from("one\ntwo\nthree\nfour\n");
+        let new = Rope::from("one\nTWO\nthree\nfour\nfive\n");
+
+        let changes = old.diff(&new);
+
+        assert_eq!(
+            changes,
+            crate::rope::changeset::ChangeSet::new(19)
+                .retain(4)
+                .insert("TWO\n")
+                .delete(4)
+                .retain(11)
+                .insert("five\n")
+        );
+    }
+
+    #[test]
+    fn diff_of_edited_clone_skips_shared_subtrees() {
+        let text = "lorem ipsum dolor sit amet\n".repeat(2000);
+        let old = Rope::from_reader(text.as_bytes()).unwrap();
+        let mut new = old.clone();
+        new.remove_range(27 * 1000..27 * 1001);
+        new.insert(27 * 1500, "inserted line\n");
+
+        let changes = old.diff(&new);
+
+        // Only the leaves around the two edits, and the path above them, aren't shared.
+        let front = skip_shared(&old.root, &new.root, 0);
+        let back = skip_shared(&old.root, &new.root, 1);
+        let back_of_edit = new.len() - 27 * 1500 - 14;
+        assert!(front <= 27 * 1000 && front > 27 * 1000 - 2 * MAX_LEAF_LEN);
+        assert!(back <= back_of_edit && back > back_of_edit - 2 * MAX_LEAF_LEN);
+        assert_eq!(old.len(), skip_shared(&old.root, &old.clone().root, 0));
+
+        let mut rope = old.clone();
+        changes.apply(&mut rope);
+        assert_eq!(new.value(), rope.value());
+        assert_eq!(old.len() - 27 + 14, changes.len_after());
+        assert!(old.diff(&old.clone()).is_empty());
+    }
+
+    #[test]
+    fn unified_hunks() {
+        let old: String = (1..=20).map(|i| format!("line {}\n", i)).collect();
+        let new = old
+            .replace("line 3\n", "line three\n")
+            .replace("line 4\n", "")
+            .replace("line 18\n", "line 18\nextra\n");
+        let (old, new) = (Rope::from(old.as_str()), Rope::from(new.as_str()));
+
+        let hunks = old.diff_hunks(&new, 2);
+
+        assert_eq!(2, hunks.len());
+        assert_eq!(
+            Hunk {
+                old_start: 0,
+                old_len: 6,
+                new_start: 0,
+                new_len: 5,
+                lines: vec![
+                    DiffLine::Context(String::from("line 1\n")),
+                    DiffLine::Context(String::from("line 2\n")),
+                    DiffLine::Removed(String::from("line 3\n")),
+                    DiffLine::Removed(String::from("line 4\n")),
+                    DiffLine::Added(String::from("line three\n")),
+                    DiffLine::Context(String::from("line 5\n")),
+                    DiffLine::Context(String::from("line 6\n")),
+                ],
+            },
+            hunks[0]
+        );
+        assert_eq!(
+            "@@ -17,4 +16,5 @@\n line 17\n line 18\n+extra\n line 19\n line 20\n",
+            hunks[1].to_string()
+        );
+        assert!(old.diff_hunks(&old, 3).is_empty());
+    }
+
+    #[test]
+    fn suffix_starting_mid_line_in_new_rope() {
+        let (old, new) = (
+            Rope::from("\n\n"),
+            Rope::from("a\nb\nc\nb\nb\n\nżb\nx\nc\n"),
+        );
+
+        let hunks = old.diff_hunks(&new, 1);
+
+        assert_eq!(1, hunks.len());
+        assert_eq!(
+            (0, 2, 0, 9),
+            (
+                hunks[0].old_start,
+                hunks[0].old_len,
+                hunks[0].new_start,
+                hunks[0].new_len
+            )
+        );
+        assert_eq!(
+            Some(&DiffLine::Added(String::from("c\n"))),
+            hunks[0].lines.last()
+        );
+    }
+
+    #[test]
+    fn hunk_without_trailing_line_break() {
+        let hunks = Rope::from("a\nb").diff_hunks(&Rope::from("a\nc"), 1);
+
+        assert_eq!(
+            "@@ -1,2 +1,2 @@\n a\n-b\n\\ No newline at end of file\n+c\n\\ No newline at end of file\n",
+            hunks[0].to_string()
+        );
+    }
+}
@@ -0,0 +1,137 @@
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RopeError {
+    // Char (or line) index past the end of the rope.
+    IndexOutOfBounds { index: usize, len: usize },
+    // Range with start past its end.
+    InvalidRange { start: usize, end: usize },
+    // Byte index falling inside of a multi-byte char.
+    NotCharBoundary(usize),
+    // UTF-16 index falling between two halves of a surrogate pair.
+    NotUtf16Boundary(usize),
+    // Bytes which are not valid UTF-8, starting at given byte offset.
+    InvalidUtf8 { offset: usize },
+    // Pattern which failed to compile, with the reason.
+    InvalidRegex(String),
+    // Content which doesn't fit in a single leaf.
+    LeafTooLong { len: usize, max: usize },
+    // Change set made for a text of different length.
+    LengthMismatch { expected: usize, found: usize },
+}
+
+pub type RopeResult<T> = Result<T, RopeError>;
+
+impl fmt::Display for RopeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RopeError::IndexOutOfBounds { index, len } => {
+                write!(f, "Index out of bounds: {} (length is {})", index, len)
+            }
+            RopeError::InvalidRange { start, end } => {
+                write!(
+                    f,
+                    "Invalid range: start {} is greater than end {}",
+                    start, end
+                )
+            }
+            RopeError::NotCharBoundary(index) => {
+                write!(f, "Byte index {} is not a char boundary", index)
+            }
+            RopeError::NotUtf16Boundary(index) => {
+                write!(f, "UTF-16 index {} splits a surrogate pair", index)
+            }
+            RopeError::InvalidUtf8 { offset } => {
+                write!(f, "Invalid UTF-8 at byte offset {}", offset)
+            }
+            RopeError::InvalidRegex(reason) => write!(f, "Invalid regex: {}", reason),
+            RopeError::LeafTooLong { len, max } => {
+                write!(f, "Leaf cannot be longer than {} bytes, got {}", max, len)
+            }
+            RopeError::LengthMismatch { expected, found } => {
+                write!(f, "Expected text of length {}, found {}", expected, found)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RopeError {}
+
+// Broken invariant of the tree, as reported by `Rope::check_invariants`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InvariantError {
+    // Branches taken from the root down to the offending node, 'L' or 'R' for each level.
+    pub path: String,
+    pub violation: Violation,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Violation {
+    // Weight of an internal node differs from the char count of its left subtree.
+    WrongWeight { expected: usize, found: usize },
+    // Line weight of an internal node differs from line breaks in its left subtree.
+    WrongLineWeight { expected: usize, found: usize },
+    // Byte or UTF-16 weight of an internal node differs from its left subtree.
+    WrongByteWeight { expected: usize, found: usize },
+    WrongUtf16Weight { expected: usize, found: usize },
+    WrongHeight { expected: usize, found: usize },
+    // Heights of the two branches differ by more than one.
+    Unbalanced { left: usize, right: usize },
+    LeafTooLong { len: usize },
+    // Leaf content which is not valid UTF-8, starting at given byte offset of the leaf.
+    InvalidUtf8 { offset: usize },
+    // Cached char count of a leaf differs from its content.
+    WrongCharCount { expected: usize, found: usize },
+    // Only the root is allowed to be an empty leaf.
+    EmptyLeaf,
+    // Length stored in the rope differs from the char count of the tree.
+    StaleLength { expected: usize, found: usize },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::WrongWeight { expected, found } => {
+                write!(f, "Weight is {} instead of {}", found, expected)
+            }
+            Violation::WrongLineWeight { expected, found } => {
+                write!(f, "Line weight is {} instead of {}", found, expected)
+            }
+            Violation::WrongByteWeight { expected, found } => {
+                write!(f, "Byte weight is {} instead of {}", found, expected)
+            }
+            Violation::WrongUtf16Weight { expected, found } => {
+                write!(f, "UTF-16 weight is {} instead of {}", found, expected)
+            }
+            Violation::WrongHeight { expected, found } => {
+                write!(f, "Height is {} instead of {}", found, expected)
+            }
+            Violation::Unbalanced { left, right } => {
+                write!(f, "Unbalanced branches of heights {} and {}", left, right)
+            }
+            Violation::LeafTooLong { len } => write!(f, "Leaf is {} bytes long", len),
+            Violation::InvalidUtf8 { offset } => {
+                write!(f, "Invalid UTF-8 at byte offset {} of leaf", offset)
+            }
+            Violation::WrongCharCount { expected, found } => {
+                write!(f, "Leaf char count is {} instead of {}", found, expected)
+            }
+            Violation::EmptyLeaf => write!(f, "Empty leaf below the root"),
+            Violation::StaleLength { expected, found } => {
+                write!(f, "Rope length is {} instead of {}", found, expected)
+            }
+        }
+    }
+}
+
+impl fmt::Display for InvariantError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{} at root", self.violation)
+        } else {
+            write!(f, "{} at {}", self.violation, self.path)
+        }
+    }
+}
+
+impl std::error::Error for InvariantError {}
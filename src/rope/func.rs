@@ -1,8 +1,9 @@
 use std::sync::Arc;
 
 use super::{
-    internal::Internal,
-    leaf::{Leaf, MAX_LEAF_LEN},
+    balance::join,
+    leaf::Leaf,
+    metrics::Metrics,
     node::{Node, Weight},
 };
 
@@ -27,9 +28,7 @@ impl<'a> Context<'a> {
         Self { index, buffer }
     }
 
-    pub(crate) fn weight_offset() {
-
-    }
+    pub(crate) fn weight_offset() {}
 }
 
 // TODO: Shouldn't leaf be immutable?
@@ -38,74 +37,46 @@ impl<'a> Context<'a> {
 pub(crate) fn insert(context: Context, leaf: &mut Leaf) -> NodeResult {
     // We are appedning here
     if context.index == leaf.weight() {
-        let remaining_space = MAX_LEAF_LEN - context.index;
-
-        if remaining_space == 0 {
-            let right = Node::from(context.buffer);
-
-            let taken_val = std::mem::take(&mut leaf.val);
-            let new_leaf = Leaf::new(taken_val, leaf.last_char_index);
-
-            let new_internal = Node::from(Internal::with_branches(Node::from(new_leaf), right));
-            return NodeResult::NewNode(new_internal);
-        }
-
-        // TODO: Merge this case with one above (remaining_space == 0)
-        if remaining_space < context.buffer.as_bytes().len() {
-            let (left, right) = context.buffer.split_at(remaining_space);
-            let mut new_internal = Internal::new();
-
-            // TODO: Make if leaf method?
-            let left_chars = left.as_bytes();
-            leaf.val[leaf.last_char_index..leaf.last_char_index + remaining_space]
-                .copy_from_slice(&left_chars);
-            leaf.last_char_index += remaining_space;
-
-            new_internal.weight = MAX_LEAF_LEN;
-
-            let vec = std::mem::take(&mut leaf.val);
-            let new_leaf = Leaf::new(vec, leaf.last_char_index);
-
-            new_internal.branches[0] = Arc::new(Node::from(new_leaf));
-            new_internal.branches[1] = Arc::new(Node::from(right));
-            return NodeResult::NewNode(Node::from(new_internal));
+        let remaining_space = leaf.available_space();
+
+        if remaining_space < context.buffer.len() {
+            // Fill the leaf up, without cutting any char in half, and put the rest next to it.
+            let mut split_point = remaining_space;
+            while !context.buffer.is_char_boundary(split_point) {
+                split_point -= 1;
+            }
+            let (left, right) = context.buffer.split_at(split_point);
+            leaf.append(left);
+
+            let left = Node::from(std::mem::take(leaf));
+            let metrics = left.metrics();
+            return NodeResult::NewNode(join(Arc::new(left), Arc::new(Node::from(right)), metrics));
         }
 
-        let val = context.buffer.as_bytes();
-        let len = val.len();
-        leaf.val[leaf.last_char_index..leaf.last_char_index + len].copy_from_slice(&val);
-        leaf.last_char_index += len;
-
+        leaf.append(context.buffer);
         NodeResult::EditedInPlace
     } else if context.index == 0 {
         // PREPEND
-        leaf.prepend(context.buffer);
-        NodeResult::EditedInPlace
-    } else {
-        if context.index > leaf.last_char_index {
-            panic!("Index out of bounds");
+        if leaf.available_space() < context.buffer.len() {
+            let left = Node::from(context.buffer);
+            let right = Node::from(std::mem::take(leaf));
+            let metrics = Metrics::of(context.buffer.as_bytes());
+            return NodeResult::NewNode(join(Arc::new(left), Arc::new(right), metrics));
         }
-        let (mut left, right) = leaf.split_at_char(context.index);
-        insert(context, &mut left);
-        NodeResult::NewNode(Node::from(Internal::with_branches(
-            Node::from(left),
-            Node::from(right),
-        )))
-    }
-}
-
-// TODO: Check if we could even consume the leaf here?
-pub(crate) fn remove_at(context: Context, leaf: &mut Leaf) -> NodeResult {
-    if leaf.last_char_index == 0 {
-        // Do nothing, as there is nothing to remove.
+        leaf.prepend(context.buffer);
         NodeResult::EditedInPlace
-    } else if context.index == leaf.last_char_index {
-        // We are at the end so just modify last char index
-        // No so fast amigo!
-        leaf.last_char_index -= 1;
+    } else if leaf.available_space() >= context.buffer.len() {
+        leaf.insert_at(context.index, context.buffer);
         NodeResult::EditedInPlace
     } else {
-        let node = leaf.remove_char_at_node(context.index);
-        NodeResult::NewNode(node)
+        // Rope checks bounds before descending, so this is only a sanity check.
+        debug_assert!(context.index <= leaf.weight(), "Index out of bounds");
+        let (mut left, right) = leaf.split_at_char(context.index);
+        let left = match insert(context, &mut left) {
+            NodeResult::NewNode(node) => node,
+            NodeResult::EditedInPlace => Node::from(left),
+        };
+        let metrics = left.metrics();
+        NodeResult::NewNode(join(Arc::new(left), Arc::new(Node::from(right)), metrics))
     }
 }
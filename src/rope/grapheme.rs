@@ -0,0 +1,47 @@
+use unicode_segmentation::{GraphemeCursor, GraphemeIncomplete};
+
+use super::node::Node;
+
+// Grapheme cluster boundaries found leaf by leaf. `GraphemeCursor` asks for neighbouring chunks
+// whenever a cluster may continue past the one it was given, so even emoji sequences spanning
+// several leaves are handled without copying them out. All offsets here are in bytes.
+
+// First boundary after `byte`, which has to be a char boundary before the end of the subtree.
+pub(crate) fn next_boundary(root: &Node, byte: usize, len: usize) -> usize {
+    let mut cursor = GraphemeCursor::new(byte, len, true);
+    let (mut chunk, mut chunk_start) = root.chunk_at_byte(byte);
+    loop {
+        match cursor.next_boundary(chunk, chunk_start) {
+            Ok(boundary) => return boundary.unwrap_or(len),
+            Err(GraphemeIncomplete::NextChunk) => {
+                chunk_start += chunk.len();
+                chunk = root.chunk_at_byte(chunk_start).0;
+            }
+            Err(GraphemeIncomplete::PreContext(end)) => provide_context(root, &mut cursor, end),
+            Err(err) => unreachable!("Unexpected grapheme cursor state: {:?}", err),
+        }
+    }
+}
+
+// Last boundary before `byte`, which has to be a char boundary after the start of the subtree.
+pub(crate) fn prev_boundary(root: &Node, byte: usize, len: usize) -> usize {
+    let mut cursor = GraphemeCursor::new(byte, len, true);
+    // Chunk ending at or after `byte`, so that there is something before the cursor.
+    let (mut chunk, mut chunk_start) = root.chunk_at_byte(byte - 1);
+    loop {
+        match cursor.prev_boundary(chunk, chunk_start) {
+            Ok(boundary) => return boundary.unwrap_or(0),
+            Err(GraphemeIncomplete::PrevChunk) => {
+                (chunk, chunk_start) = root.chunk_at_byte(chunk_start - 1);
+            }
+            Err(GraphemeIncomplete::PreContext(end)) => provide_context(root, &mut cursor, end),
+            Err(err) => unreachable!("Unexpected grapheme cursor state: {:?}", err),
+        }
+    }
+}
+
+// Hands over the chunk ending at `end`, which is always where some leaf starts.
+fn provide_context(root: &Node, cursor: &mut GraphemeCursor, end: usize) {
+    let (chunk, start) = root.chunk_at_byte(end - 1);
+    cursor.provide_context(&chunk[..end - start], start);
+}
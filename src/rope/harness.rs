@@ -0,0 +1,129 @@
+// Model-based test harness shared by property tests and the fuzz target. Random sequences of
+// edits run against both a `Rope` and a plain `String`, which serves as an oracle. After every
+// step content, length and lines have to match, and the tree has to keep its invariants.
+
+use super::rope::Rope;
+
+// Indices are clamped into the rope at the time they're applied, so any value is fine.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub enum Op {
+    Insert(usize, String),
+    Append(String),
+    RemoveAt(usize),
+    RemoveRange(usize, usize),
+    Concat(String),
+    SplitAt(usize, bool),
+    Rebalance,
+    // Edits a clone at given index, which must not affect the original.
+    Clone(usize, String),
+}
+
+// Builds a rope from `initial` and applies all ops, checking it against the oracle at every step.
+pub fn run(initial: &str, ops: Vec<Op>) {
+    let mut rope = Rope::from(initial);
+    let mut oracle = initial.to_string();
+    check(&rope, &oracle);
+
+    for op in ops {
+        apply(&mut rope, &mut oracle, op);
+        check(&rope, &oracle);
+    }
+}
+
+pub(crate) fn byte_index(text: &str, char_index: usize) -> usize {
+    text.char_indices()
+        .nth(char_index)
+        .map_or(text.len(), |(idx, _)| idx)
+}
+
+pub fn apply(rope: &mut Rope, oracle: &mut String, op: Op) {
+    let len = oracle.chars().count();
+    match op {
+        Op::Insert(index, text) => {
+            let index = index % (len + 1);
+            rope.insert(index, &text);
+            oracle.insert_str(byte_index(oracle, index), &text);
+        }
+        Op::Append(text) => {
+            rope.append(&text);
+            oracle.push_str(&text);
+        }
+        Op::RemoveAt(index) => {
+            if len > 0 {
+                let index = index % len;
+                rope.remove_at(index);
+                oracle.remove(byte_index(oracle, index));
+            }
+        }
+        Op::RemoveRange(a, b) => {
+            let (a, b) = (a % (len + 1), b % (len + 1));
+            let range = a.min(b)..a.max(b);
+            rope.remove_range(range.clone());
+            oracle.replace_range(
+                byte_index(oracle, range.start)..byte_index(oracle, range.end),
+                "",
+            );
+        }
+        Op::Concat(text) => {
+            *rope = std::mem::replace(rope, Rope::new()).concat(Rope::from(text.as_str()));
+            oracle.push_str(&text);
+        }
+        Op::SplitAt(index, keep_left) => {
+            let index = index % (len + 1);
+            let (left, right) = rope.split_at(index);
+            let at = byte_index(oracle, index);
+            if keep_left {
+                *rope = left;
+                oracle.truncate(at);
+            } else {
+                *rope = right;
+                oracle.replace_range(..at, "");
+            }
+        }
+        Op::Rebalance => rope.rebalance(),
+        Op::Clone(index, text) => {
+            let mut copy = rope.clone();
+            let mut copy_oracle = oracle.clone();
+            apply(&mut copy, &mut copy_oracle, Op::RemoveAt(index));
+            apply(&mut copy, &mut copy_oracle, Op::Insert(index, text));
+            check(&copy, &copy_oracle);
+        }
+    }
+}
+
+// Compares the rope with the oracle and checks invariants of the whole tree. Panics on mismatch.
+pub fn check(rope: &Rope, oracle: &str) {
+    let len = oracle.chars().count();
+    assert_eq!(oracle, rope.value());
+    assert_eq!(len, rope.len());
+    if let Err(err) = rope.check_invariants() {
+        panic!("{}\n{}", err, rope.debug_tree());
+    }
+    assert!(rope.chars().rev().eq(oracle.chars().rev()));
+    assert!(rope.bytes().eq(oracle.bytes()));
+    assert_eq!(oracle.len(), rope.len_bytes());
+    assert_eq!(oracle.encode_utf16().count(), rope.len_utf16());
+
+    // Every few chars, so that long texts stay cheap to check.
+    let mut utf16 = 0;
+    for (idx, (byte, c)) in oracle.char_indices().enumerate() {
+        if idx % 31 == 0 {
+            assert_eq!(byte, rope.char_to_byte(idx));
+            assert_eq!(idx, rope.byte_to_char(byte));
+            assert_eq!(utf16, rope.char_to_utf16(idx));
+            assert_eq!(idx, rope.utf16_to_char(utf16));
+        }
+        utf16 += c.len_utf16();
+    }
+
+    let lines: Vec<&str> = oracle.split('\n').collect();
+    assert_eq!(lines.len(), rope.line_count());
+    let mut line_start = 0;
+    for (idx, line) in lines.iter().enumerate() {
+        assert_eq!(*line, rope.line(idx));
+        assert_eq!(line_start, rope.line_to_char(idx));
+        assert_eq!(idx, rope.char_to_line(line_start));
+        line_start += line.chars().count() + 1;
+    }
+}
@@ -0,0 +1,308 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    ops::Range,
+    sync::Arc,
+    time::SystemTime,
+};
+
+use super::{
+    diff::{common_prefix, common_suffix},
+    node::{Node, ARC_NODE_SIZE},
+    rope::Rope,
+};
+
+// Immutable version of a rope. Cloning a rope only bumps the reference count of its root, and
+// edits copy just the nodes on their path, so versions share everything they have in common.
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    id: usize,
+    time: SystemTime,
+    rope: Rope,
+}
+
+impl Snapshot {
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn time(&self) -> SystemTime {
+        self.time
+    }
+
+    pub fn rope(&self) -> &Rope {
+        &self.rope
+    }
+}
+
+// Difference between two versions: chars in `range` of the older one were replaced by `text`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SnapshotDiff {
+    pub range: Range<usize>,
+    pub text: String,
+}
+
+impl SnapshotDiff {
+    pub fn is_empty(&self) -> bool {
+        self.range.is_empty() && self.text.is_empty()
+    }
+}
+
+// Ordered list of snapshots, oldest first. With a limit set, the oldest ones are dropped once
+// there are more of them.
+#[derive(Clone, Debug, Default)]
+pub struct RopeHistory {
+    snapshots: VecDeque<Snapshot>,
+    next_id: usize,
+    limit: Option<usize>,
+}
+
+impl RopeHistory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_limit(limit: usize) -> Self {
+        Self {
+            limit: Some(limit),
+            ..Self::default()
+        }
+    }
+
+    // Records current version of the rope. Returns id of the new snapshot.
+    pub fn record(&mut self, rope: &Rope) -> usize {
+        self.record_at(rope, SystemTime::now())
+    }
+
+    pub fn record_at(&mut self, rope: &Rope, time: SystemTime) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.snapshots.push_back(Snapshot {
+            id,
+            time,
+            rope: rope.clone(),
+        });
+
+        if let Some(limit) = self.limit {
+            while self.snapshots.len() > limit {
+                self.snapshots.pop_front();
+            }
+        }
+        id
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &Snapshot> {
+        self.snapshots.iter()
+    }
+
+    pub fn latest(&self) -> Option<&Snapshot> {
+        self.snapshots.back()
+    }
+
+    pub fn get(&self, id: usize) -> Option<&Snapshot> {
+        // Ids are increasing, so the position can be found by binary search.
+        let idx = self.snapshots.binary_search_by_key(&id, |s| s.id).ok()?;
+        self.snapshots.get(idx)
+    }
+
+    // Last snapshot recorded at or before given time, i.e. what the rope looked like back then.
+    pub fn at(&self, time: SystemTime) -> Option<&Snapshot> {
+        self.snapshots.iter().rev().find(|s| s.time <= time)
+    }
+
+    // Rope as it was in given snapshot.
+    pub fn restore(&self, id: usize) -> Option<Rope> {
+        self.get(id).map(|s| s.rope.clone())
+    }
+
+    // What changed between two snapshots, as a single replaced range.
+    pub fn diff(&self, from: usize, to: usize) -> Option<SnapshotDiff> {
+        Some(diff(&self.get(from)?.rope, &self.get(to)?.rope))
+    }
+
+    // Bytes allocated by all snapshots together. Nodes shared between them are counted once.
+    pub fn heap_size(&self) -> usize {
+        let mut seen = HashSet::new();
+        self.snapshots
+            .iter()
+            .map(|s| shared_heap_size(&s.rope.root, &mut seen))
+            .sum()
+    }
+}
+
+// Skips the common prefix and suffix of both ropes, whatever is left in between has changed.
+// Subtrees the snapshots share are skipped without comparing them.
+pub(crate) fn diff(old: &Rope, new: &Rope) -> SnapshotDiff {
+    if Arc::ptr_eq(&old.root, &new.root) {
+        return SnapshotDiff {
+            range: 0..0,
+            text: String::new(),
+        };
+    }
+
+    let prefix = common_prefix(old, new);
+    let max_suffix = old.len().min(new.len()) - prefix;
+    let suffix = common_suffix(old, new, max_suffix);
+
+    SnapshotDiff {
+        range: prefix..old.len() - suffix,
+        text: new.slice(prefix..new.len() - suffix).to_string(),
+    }
+}
+
+fn shared_heap_size(node: &Arc<Node>, seen: &mut HashSet<*const Node>) -> usize {
+    if !seen.insert(Arc::as_ptr(node)) {
+        return 0;
+    }
+    ARC_NODE_SIZE
+        + match node.as_ref() {
+            Node::Leaf(leaf) => leaf.heap_size(),
+            Node::Internal(internal) => internal
+                .branches
+                .iter()
+                .map(|branch| shared_heap_size(branch, seen))
+                .sum(),
+        }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::{RopeHistory, SnapshotDiff};
+    use crate::rope::rope::Rope;
+
+    #[test]
+    fn record_and_restore() {
+        let mut history = RopeHistory::new();
+        let mut rope = Rope::from("Hello World");
+
+        let first = history.record(&rope);
+        rope.insert(6, "beautiful ");
+        let second = history.record(&rope);
+        rope.remove_range(0..6);
+
+        assert_eq!(2, history.len());
+        assert_eq!("Hello World", history.restore(first).unwrap().value());
+        assert_eq!(
+            "Hello beautiful World",
+            history.restore(second).unwrap().value()
+        );
+        assert_eq!(second, history.latest().unwrap().id());
+        assert!(history.restore(second + 1).is_none());
+    }
+
+    #[test]
+    fn snapshot_at_time() {
+        let mut history = RopeHistory::new();
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let mut rope = Rope::from("one");
+
+        history.record_at(&rope, start);
+        rope.append(" two");
+        history.record_at(&rope, start + Duration::from_secs(60));
+        rope.append(" three");
+        history.record_at(&rope, start + Duration::from_secs(600));
+
+        let ten_minutes_ago = start + Duration::from_secs(599);
+        assert_eq!(
+            "one two",
+            history.at(ten_minutes_ago).unwrap().rope().value()
+        );
+        assert_eq!(
+            "one two three",
+            history
+                .at(start + Duration::from_secs(600))
+                .unwrap()
+                .rope()
+                .value()
+        );
+        assert!(history.at(start - Duration::from_secs(1)).is_none());
+    }
+
+    #[test]
+    fn diff_between_snapshots() {
+        let mut history = RopeHistory::new();
+        let mut rope = Rope::from("zażółć gęślą jaźń");
+
+        let first = history.record(&rope);
+        rope.remove_range(7..12);
+        rope.insert(7, "żółte");
+        let second = history.record(&rope);
+
+        assert_eq!(
+            Some(SnapshotDiff {
+                range: 7..12,
+                text: String::from("żółte"),
+            }),
+            history.diff(first, second)
+        );
+        assert_eq!(
+            Some(SnapshotDiff {
+                range: 7..12,
+                text: String::from("gęślą"),
+            }),
+            history.diff(second, first)
+        );
+        assert!(history.diff(first, first).unwrap().is_empty());
+    }
+
+    #[test]
+    fn diff_of_repeated_text() {
+        let mut history = RopeHistory::new();
+        let mut rope = Rope::from("aaaa");
+
+        let first = history.record(&rope);
+        rope.insert(2, "a");
+        let second = history.record(&rope);
+
+        let diff = history.diff(first, second).unwrap();
+        assert_eq!(4..4, diff.range);
+        assert_eq!("a", diff.text);
+    }
+
+    #[test]
+    fn limit_drops_oldest_snapshots() {
+        let mut history = RopeHistory::with_limit(2);
+        let mut rope = Rope::new();
+
+        for c in ["a", "b", "c"] {
+            rope.append(c);
+            history.record(&rope);
+        }
+
+        assert_eq!(2, history.len());
+        assert!(history.get(0).is_none());
+        assert_eq!("ab", history.get(1).unwrap().rope().value());
+        assert_eq!("abc", history.get(2).unwrap().rope().value());
+    }
+
+    #[test]
+    fn snapshots_share_memory() {
+        let text = "lorem ipsum dolor sit amet\n".repeat(2000);
+        let mut rope = Rope::from_reader(text.as_bytes()).unwrap();
+        let mut history = RopeHistory::new();
+
+        for i in 0..100 {
+            rope.insert(i * 500, "edit");
+            history.record(&rope);
+        }
+
+        // Every snapshot on its own is as big as the whole text, but together they take only
+        // a bit more than one, as each edit copies a single path.
+        let single = rope.heap_size();
+        assert!(
+            history.heap_size() < 3 * single,
+            "{} vs {}",
+            history.heap_size(),
+            single
+        );
+    }
+}
@@ -1,12 +1,22 @@
 use std::sync::Arc;
 
-use super::node::{Node, Weight};
+use super::{
+    metrics::Metrics,
+    node::{Node, Weight},
+};
 
 // TODO: Make this at max 4kb
 #[derive(Clone, Debug)]
 #[repr(C)]
 pub struct Internal {
     pub(super) weight: usize,
+    // Number of line breaks in the left branch.
+    pub(super) line_weight: usize,
+    // Bytes and UTF-16 code units in the left branch.
+    pub(super) byte_weight: usize,
+    pub(super) utf16_weight: usize,
+    // Length of the longest path down to a leaf.
+    pub(super) height: usize,
     pub(super) branches: [Arc<Node>; 2],
 }
 
@@ -14,23 +24,67 @@ impl Internal {
     pub fn new() -> Self {
         Self {
             weight: 0,
+            line_weight: 0,
+            byte_weight: 0,
+            utf16_weight: 0,
+            height: 1,
             branches: [Arc::new(Node::from("")), Arc::new(Node::from(""))],
         }
     }
 
     pub fn with_branches(left: Node, right: Node) -> Self {
+        // Weight is the length of the whole left subtree, not just of its left branch.
+        let metrics = left.metrics();
+        Self::with_shared_branches(Arc::new(left), Arc::new(right), metrics)
+    }
+
+    // `left_metrics` has to describe the whole left subtree.
+    pub(super) fn with_shared_branches(
+        left: Arc<Node>,
+        right: Arc<Node>,
+        left_metrics: Metrics,
+    ) -> Self {
         Self {
-            weight: left.weight(),
-            branches: [Arc::new(left), Arc::new(right)],
+            weight: left_metrics.chars,
+            line_weight: left_metrics.newlines,
+            byte_weight: left_metrics.bytes,
+            utf16_weight: left_metrics.utf16,
+            height: 1 + left.height().max(right.height()),
+            branches: [left, right],
         }
     }
 
-    pub fn with_branches_and_weight(left: Node, right: Node, weight: usize) -> Self {
-        Self {
-            weight,
-            branches: [Arc::new(left), Arc::new(right)],
+    // Size of the left subtree in every unit.
+    pub(super) fn left_metrics(&self) -> Metrics {
+        Metrics {
+            chars: self.weight,
+            newlines: self.line_weight,
+            bytes: self.byte_weight,
+            utf16: self.utf16_weight,
         }
     }
+
+    pub(super) fn set_left_metrics(&mut self, metrics: Metrics) {
+        self.weight = metrics.chars;
+        self.line_weight = metrics.newlines;
+        self.byte_weight = metrics.bytes;
+        self.utf16_weight = metrics.utf16;
+    }
+
+    pub(super) fn update_height(&mut self) {
+        self.height = 1 + self.branches[0].height().max(self.branches[1].height());
+    }
+
+    // Difference between heights of the left and the right branch.
+    pub(super) fn balance_factor(&self) -> isize {
+        self.branches[0].height() as isize - self.branches[1].height() as isize
+    }
+
+    // Moves the branch out, cloning it only if it's shared with another rope.
+    pub(super) fn take_branch(&mut self, idx: usize) -> Node {
+        let branch = std::mem::replace(&mut self.branches[idx], Arc::new(Node::from("")));
+        Arc::unwrap_or_clone(branch)
+    }
 }
 
 impl Weight for Internal {
@@ -1,43 +1,211 @@
-use super::{leaf::Leaf, node::Node};
+use std::ops::Range;
 
-// TODO: Get it to not clone?
-pub struct LeafIterator<'a> {
-    nodes: Vec<&'a Leaf>,
-    index: usize,
+use super::{node::Node, node::Weight};
+
+// Iterates over leaves overlapping with a char range, clipping the first and the last one.
+// Both ends keep a stack of subtrees still to be visited, together with the char offset at
+// which each of them starts. Seeking to either end is O(log n), every next chunk is O(1)
+// amortized.
+#[derive(Clone, Debug)]
+pub struct Chunks<'a> {
+    front: Vec<(&'a Node, usize)>,
+    back: Vec<(&'a Node, usize)>,
+    start: usize,
+    end: usize,
 }
 
-impl<'a> LeafIterator<'a> {
-    pub fn new(node: &'a Node) -> Self {
-        // TODO: Maybe try and store somewhere the count of leafs?
-        let mut nodes = vec![];
-        Self::traverse(node, &mut nodes);
-        Self { nodes, index: 0 }
+impl<'a> Chunks<'a> {
+    pub(crate) fn new(root: &'a Node, range: Range<usize>) -> Self {
+        Self {
+            front: Self::seek_front(root, range.start),
+            back: Self::seek_back(root, range.end),
+            start: range.start,
+            end: range.end,
+        }
     }
 
-    fn traverse(node: &'a Node, nodes: &mut Vec<&'a Leaf>) {
-        match node {
-            Node::Leaf(node) => {
-                nodes.push(&node);
+    // Descends to the leaf containing `index`, remembering right siblings on the way.
+    fn seek_front(root: &'a Node, index: usize) -> Vec<(&'a Node, usize)> {
+        let mut stack = vec![];
+        let mut node = root;
+        let mut offset = 0;
+
+        while let Node::Internal(internal) = node {
+            if index < offset + internal.weight {
+                stack.push((internal.branches[1].as_ref(), offset + internal.weight));
+                node = &internal.branches[0];
+            } else {
+                offset += internal.weight;
+                node = &internal.branches[1];
             }
-            Node::Internal(internal) => {
-                Self::traverse(&internal.branches[0], nodes);
-                Self::traverse(&internal.branches[1], nodes);
+        }
+        stack.push((node, offset));
+        stack
+    }
+
+    // Descends to the leaf containing char right before `index`, remembering left siblings.
+    fn seek_back(root: &'a Node, index: usize) -> Vec<(&'a Node, usize)> {
+        let mut stack = vec![];
+        let mut node = root;
+        let mut offset = 0;
+
+        while let Node::Internal(internal) = node {
+            if index <= offset + internal.weight {
+                node = &internal.branches[0];
+            } else {
+                stack.push((internal.branches[0].as_ref(), offset));
+                offset += internal.weight;
+                node = &internal.branches[1];
             }
         }
+        stack.push((node, offset));
+        stack
     }
 }
 
-impl<'a> Iterator for LeafIterator<'a> {
+impl<'a> Iterator for Chunks<'a> {
     type Item = &'a str;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.index == self.nodes.len() {
-            None
-        } else {
-            let node = self.nodes[self.index];
-            self.index += 1;
-            let val = std::str::from_utf8(&node.get_char_bytes());
-            Some(val.unwrap())
+        while self.start < self.end {
+            let (node, offset) = self.front.pop()?;
+            match node {
+                Node::Internal(internal) => {
+                    let [left, right] = &internal.branches;
+                    self.front.push((right, offset + internal.weight));
+                    self.front.push((left, offset));
+                }
+                Node::Leaf(leaf) => {
+                    let leaf_end = (offset + leaf.weight()).min(self.end);
+                    if leaf_end <= self.start {
+                        continue;
+                    }
+                    let chunk = leaf.slice(self.start - offset, leaf_end - offset);
+                    self.start = leaf_end;
+                    return Some(chunk);
+                }
+            }
+        }
+        None
+    }
+}
+
+impl<'a> DoubleEndedIterator for Chunks<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        while self.start < self.end {
+            let (node, offset) = self.back.pop()?;
+            match node {
+                Node::Internal(internal) => {
+                    let [left, right] = &internal.branches;
+                    self.back.push((left, offset));
+                    self.back.push((right, offset + internal.weight));
+                }
+                Node::Leaf(leaf) => {
+                    let leaf_start = offset.max(self.start);
+                    let leaf_end = (offset + leaf.weight()).min(self.end);
+                    if leaf_end <= leaf_start {
+                        continue;
+                    }
+                    let chunk = leaf.slice(leaf_start - offset, leaf_end - offset);
+                    self.end = leaf_start;
+                    return Some(chunk);
+                }
+            }
+        }
+        None
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Chars<'a> {
+    chunks: Chunks<'a>,
+    front: std::str::Chars<'a>,
+    back: std::str::Chars<'a>,
+}
+
+impl<'a> Chars<'a> {
+    pub(crate) fn new(chunks: Chunks<'a>) -> Self {
+        Self {
+            chunks,
+            front: "".chars(),
+            back: "".chars(),
+        }
+    }
+}
+
+impl<'a> Iterator for Chars<'a> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(c) = self.front.next() {
+                return Some(c);
+            }
+            match self.chunks.next() {
+                Some(chunk) => self.front = chunk.chars(),
+                None => return self.back.next(),
+            }
+        }
+    }
+}
+
+impl<'a> DoubleEndedIterator for Chars<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(c) = self.back.next_back() {
+                return Some(c);
+            }
+            match self.chunks.next_back() {
+                Some(chunk) => self.back = chunk.chars(),
+                None => return self.front.next_back(),
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Bytes<'a> {
+    chunks: Chunks<'a>,
+    front: std::str::Bytes<'a>,
+    back: std::str::Bytes<'a>,
+}
+
+impl<'a> Bytes<'a> {
+    pub(crate) fn new(chunks: Chunks<'a>) -> Self {
+        Self {
+            chunks,
+            front: "".bytes(),
+            back: "".bytes(),
+        }
+    }
+}
+
+impl<'a> Iterator for Bytes<'a> {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(b) = self.front.next() {
+                return Some(b);
+            }
+            match self.chunks.next() {
+                Some(chunk) => self.front = chunk.bytes(),
+                None => return self.back.next(),
+            }
+        }
+    }
+}
+
+impl<'a> DoubleEndedIterator for Bytes<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(b) = self.back.next_back() {
+                return Some(b);
+            }
+            match self.chunks.next_back() {
+                Some(chunk) => self.back = chunk.bytes(),
+                None => return self.front.next_back(),
+            }
         }
     }
 }
@@ -1,10 +1,16 @@
+use std::{ops::Range, sync::Arc};
+
+use memmap2::Mmap;
+
 use super::{
-    internal::Internal,
-    node::{Node, Weight},
+    content::Content,
+    error::{RopeError, RopeResult},
+    metrics::{count_surrogate_pairs, Metrics},
+    node::Weight,
 };
 
 // We assume that one page is 4096 bytes long.
-// Vec pointer + last_char_index + vec len + vec capacity
+// Vec pointer + vec len + vec capacity + char count
 pub const TOTAL_BYTES: usize = 1024;
 
 //pub const INTERNAL_SIZE: usize = std::mem::size_of::<usize>() + std::mem::size_of::<[u8; 2]>();
@@ -18,103 +24,262 @@ pub const NODE_SIZE: usize = LEAF_SIZE + INTERNAL_SIZE;
 //pub const MAX_LEAF_LEN: usize = (TOTAL_BYTES - NODE_SIZE) / 2;
 pub const MAX_LEAF_LEN: usize = TOTAL_BYTES - NODE_SIZE;
 
+// Leaves shorter than this get merged with their neighbours whenever the two fit together.
+// A subtree of at most this many chars always fits in a single leaf.
+pub const MIN_LEAF_LEN: usize = MAX_LEAF_LEN / 4;
+
+// Leaves reading from a memory map take no heap space for their content, so they cover much more
+// of the file and keep the tree small. Each is cut into regular leaves before its first edit.
+pub const MAX_MAPPED_LEAF_LEN: usize = 64 * 1024;
+
 // TODO: This should be immutable eventually... reallly?
-#[derive(Clone, Debug)]
+// Holds only its content, growing on demand up to MAX_LEAF_LEN bytes. Content may also be a
+// range of a memory-mapped file, copied out on the first edit.
+// Number of chars is cached, so weight of a leaf is known without decoding it.
+#[derive(Clone, Debug, Default)]
 #[repr(C)]
 pub struct Leaf {
-    pub(super) val: Vec<u8>,
-    pub(super) last_char_index: usize,
+    pub(super) val: Content,
+    chars: usize,
 }
 
 impl Leaf {
-    pub fn new(val: Vec<u8>, last_char_index: usize) -> Self {
-        Self {
-            val,
-            last_char_index,
+    // Leaf reading its content from the map. The range has to be valid UTF-8.
+    pub(crate) fn mapped(map: &Arc<Mmap>, range: Range<usize>) -> Leaf {
+        Leaf::try_mapped(map, range).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    // Leaf holding a copy of the bytes, panicking if they don't fit. `try_from` reports it instead.
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Leaf {
+        Leaf::try_from(bytes).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub(crate) fn try_mapped(map: &Arc<Mmap>, range: Range<usize>) -> RopeResult<Leaf> {
+        check_len(range.len(), MAX_MAPPED_LEAF_LEN)?;
+        Ok(Leaf {
+            chars: count_chars(&map[range.clone()]),
+            val: Content::Mapped(map.clone(), range),
+        })
+    }
+
+    pub fn is_mapped(&self) -> bool {
+        self.val.is_mapped()
+    }
+
+    // Longer than a regular leaf, which only mapped leaves can be.
+    pub fn is_oversized(&self) -> bool {
+        self.val.len() > MAX_LEAF_LEN
+    }
+
+    // Regular leaves covering the same content, still reading from the map.
+    pub(crate) fn cut(&self) -> Vec<Leaf> {
+        match &self.val {
+            Content::Mapped(map, range) => mapped_pieces(map, range.clone(), MAX_LEAF_LEN)
+                .map(|piece| Leaf::mapped(map, piece))
+                .collect(),
+            Content::Owned(_) => vec![self.clone()],
         }
     }
 
     pub fn available_space(&self) -> usize {
-        MAX_LEAF_LEN - self.last_char_index
+        MAX_LEAF_LEN.saturating_sub(self.val.len())
     }
 
     pub fn get_char_bytes(&self) -> &[u8] {
-        &self.val[..self.last_char_index]
+        &self.val
     }
 
-    pub fn byte_position_of_char_at(&self, index: usize) -> usize {
-        if self.last_char_index == 0 {
-            return 0;
+    pub fn is_empty(&self) -> bool {
+        self.val.is_empty()
+    }
+
+    pub fn is_undersized(&self) -> bool {
+        self.val.len() < MIN_LEAF_LEN
+    }
+
+    // Merges two neighbouring leaves into one, if either of them is undersized and they fit
+    // together.
+    pub(crate) fn merge(left: &Leaf, right: &Leaf) -> Option<Leaf> {
+        let fits = left.val.len() + right.val.len() <= MAX_LEAF_LEN;
+        if !fits || !(left.is_undersized() || right.is_undersized()) {
+            return None;
         }
 
-        unsafe {
-            std::str::from_utf8_unchecked(&self.val[..self.last_char_index])
-                .chars()
-                .take(index)
-                .map(|c| c.len_utf8())
-                .sum()
+        let mut val = Vec::with_capacity(left.val.len() + right.val.len());
+        val.extend_from_slice(&left.val);
+        val.extend_from_slice(&right.val);
+        Some(Leaf {
+            val: Content::Owned(val),
+            chars: left.chars + right.chars,
+        })
+    }
+
+    // Bytes allocated for the content.
+    pub fn heap_size(&self) -> usize {
+        self.val.heap_size()
+    }
+
+    pub fn is_ascii(&self) -> bool {
+        self.chars == self.val.len()
+    }
+
+    pub fn byte_position_of_char_at(&self, index: usize) -> usize {
+        // Every char is a single byte, so there is nothing to decode.
+        if self.is_ascii() {
+            return index.min(self.val.len());
         }
+
+        // Chars start at every byte that isn't a continuation byte.
+        self.val
+            .iter()
+            .enumerate()
+            .filter(|(_, &b)| !is_continuation(b))
+            .nth(index)
+            .map_or(self.val.len(), |(pos, _)| pos)
     }
 
-    pub(crate) fn split_at_char(&mut self, index: usize) -> (Leaf, Leaf) {
+    pub(crate) fn split_at_char(&self, index: usize) -> (Leaf, Leaf) {
         let split_point = self.byte_position_of_char_at(index);
-        let mut current_leaf_val = std::mem::take(&mut self.val);
+        // Both halves of a mapped leaf still point into the map.
+        if let Content::Mapped(map, range) = &self.val {
+            let middle = range.start + split_point;
+            return (
+                Leaf::mapped(map, range.start..middle),
+                Leaf::mapped(map, middle..range.end),
+            );
+        }
+        let (left, right) = self.get_char_bytes().split_at(split_point);
 
-        let (actual_data, _) = current_leaf_val.split_at_mut(self.last_char_index);
+        (Leaf::from_bytes(left), Leaf::from_bytes(right))
+    }
+
+    pub fn newlines(&self) -> usize {
+        count_newlines(self.get_char_bytes())
+    }
 
-        let (left, right) = actual_data.split_at(split_point);
+    pub(crate) fn metrics(&self) -> Metrics {
+        Metrics {
+            chars: self.chars,
+            newlines: self.newlines(),
+            bytes: self.val.len(),
+            utf16: self.chars + count_surrogate_pairs(&self.val),
+        }
+    }
 
-        (Leaf::from(left), Leaf::from(right))
+    // Number of line breaks among the first `index` chars.
+    pub(crate) fn newlines_before(&self, index: usize) -> usize {
+        count_newlines(&self.val[..self.byte_position_of_char_at(index)])
     }
 
-    // TODO: Now it's 2x O(n). Make it single pass.
-    // This splits the leaf, to: (Leaf(0,a), Leaf(b, last_char_index))
-    pub(crate) fn remove_char_at(&mut self, index: usize) -> (Leaf, Leaf) {
-        let a = self.byte_position_of_char_at(index);
-        let b = self.byte_position_of_char_at(index + 1);
+    // Char index right after the n-th (1-based) line break.
+    pub(crate) fn char_after_newline(&self, n: usize) -> usize {
+        let mut seen = 0;
+        for (pos, &b) in self.val.iter().enumerate() {
+            if b == b'\n' {
+                seen += 1;
+                if seen == n {
+                    return count_chars(&self.val[..=pos]);
+                }
+            }
+        }
+        panic!("Leaf has only {} line breaks", seen);
+    }
 
-        let mut current_leaf_val = std::mem::take(&mut self.val);
-        let (actual_data, _) = current_leaf_val.split_at_mut(self.last_char_index);
-        let (left, right) = actual_data.split_at(a);
-        let (_, right) = right.split_at(b - a);
+    // Char index of given byte offset, None if it falls inside of a char.
+    pub(crate) fn byte_to_char(&self, byte: usize) -> Option<usize> {
+        if byte > self.val.len() || self.val.get(byte).is_some_and(|&b| is_continuation(b)) {
+            return None;
+        }
+        Some(count_chars(&self.val[..byte]))
+    }
 
-        (Leaf::from(left), Leaf::from(right))
+    // UTF-16 code units among the first `index` chars.
+    pub(crate) fn char_to_utf16(&self, index: usize) -> usize {
+        index + count_surrogate_pairs(&self.val[..self.byte_position_of_char_at(index)])
     }
 
-    pub(crate) fn remove_char_at_node(&mut self, index: usize) -> Node {
-        println!("Removing char at: {}", index);
-        if index == 0 {
-            println!("Removing first char");
-            let idx = self.byte_position_of_char_at(1);
-            return Node::Leaf(Leaf::from(&self.val[idx..self.last_char_index]));
+    // Char index of given UTF-16 offset, None if it falls between two halves of a surrogate pair.
+    pub(crate) fn utf16_to_char(&self, units: usize) -> Option<usize> {
+        let mut seen = 0;
+        let starts = self.val.iter().filter(|&&b| !is_continuation(b));
+        for (idx, &b) in starts.enumerate() {
+            if seen >= units {
+                return (seen == units).then_some(idx);
+            }
+            // Only chars taking four bytes are outside of the BMP.
+            seen += if b >= 0xf0 { 2 } else { 1 };
         }
-        println!("Removing further.");
-        let (left, right) = self.remove_char_at(index);
-        Node::Internal(Internal::with_branches(Node::Leaf(left), Node::Leaf(right)))
+        (seen == units).then_some(self.chars)
+    }
+
+    pub(crate) fn as_str(&self) -> &str {
+        to_str(&self.val, 0..self.val.len())
+    }
+
+    // Chars in [start, end) of this leaf.
+    pub(crate) fn slice(&self, start: usize, end: usize) -> &str {
+        let a = self.byte_position_of_char_at(start);
+        let b = self.byte_position_of_char_at(end);
+        to_str(&self.val, a..b)
+    }
+
+    // Removes chars in [start, end) in place by shifting the tail left.
+    // Returns metrics of the removed text.
+    pub(crate) fn remove_range(&mut self, start: usize, end: usize) -> Metrics {
+        let a = self.byte_position_of_char_at(start);
+        let b = self.byte_position_of_char_at(end);
+        let removed = Metrics::of(&self.val[a..b]);
+
+        self.val.to_mut().drain(a..b);
+        self.chars -= removed.chars;
+        removed
+    }
+
+    pub(crate) fn append(&mut self, buffer: &str) {
+        self.val.to_mut().extend_from_slice(buffer.as_bytes());
+        self.chars += buffer.chars().count();
     }
 
     pub(crate) fn prepend(&mut self, buffer: &str) {
-        let mut new_node_val = [0; MAX_LEAF_LEN];
+        self.val.to_mut().splice(0..0, buffer.bytes());
+        self.chars += buffer.chars().count();
+    }
+
+    // Inserts buffer before char at given index, growing the leaf in place.
+    pub(crate) fn insert_at(&mut self, index: usize, buffer: &str) {
+        let position = self.byte_position_of_char_at(index);
+        self.val.to_mut().splice(position..position, buffer.bytes());
+        self.chars += buffer.chars().count();
+    }
+}
 
-        let val = buffer.as_bytes();
-        let start = val.len();
-        new_node_val[..start].copy_from_slice(val);
+pub(crate) fn count_newlines(bytes: &[u8]) -> usize {
+    bytes.iter().filter(|b| **b == b'\n').count()
+}
 
-        new_node_val[start..start + self.last_char_index]
-            .copy_from_slice(&self.val[..self.last_char_index]);
+// Number of chars in valid UTF-8, i.e. bytes which are not continuation bytes (0b10xxxxxx).
+pub(crate) fn count_chars(bytes: &[u8]) -> usize {
+    bytes.iter().filter(|b| !is_continuation(**b)).count()
+}
 
-        self.val = new_node_val.to_vec();
-        self.last_char_index += start;
+fn is_continuation(byte: u8) -> bool {
+    (byte as i8) < -0x40
+}
+
+// Owned content was validated when it was added. Mapped content was validated when its file was
+// opened, and is again on every access, so even a snapshot changed behind the rope's back (say by
+// a debugger) cannot turn into an invalid `&str`.
+fn to_str(val: &Content, range: Range<usize>) -> &str {
+    match val {
+        Content::Owned(val) => unsafe { std::str::from_utf8_unchecked(&val[range]) },
+        Content::Mapped(..) => std::str::from_utf8(&val[range])
+            .unwrap_or_else(|_| panic!("Mapped file was changed after it was opened")),
     }
 }
 
 impl Weight for Leaf {
     fn weight(&self) -> usize {
-        unsafe {
-            std::str::from_utf8_unchecked(self.get_char_bytes())
-                .chars()
-                .count()
-        }
+        self.chars
     }
 }
 
@@ -125,55 +290,197 @@ impl From<&str> for Leaf {
         // TODO: This should probably split and return Node - maybe leaf, maybe internal. So
         // move it to node.rs implementation.
         // ^ it's actually done in Node::from
-        Leaf::from(value.as_bytes())
+        Leaf::from_bytes(value.as_bytes())
     }
 }
 
-impl From<&[u8]> for Leaf {
-    fn from(value: &[u8]) -> Self {
-        if value.len() > MAX_LEAF_LEN {
-            panic!("Leaf cannot be longer than {} bytes", MAX_LEAF_LEN);
+impl TryFrom<&[u8]> for Leaf {
+    type Error = RopeError;
+
+    fn try_from(value: &[u8]) -> RopeResult<Self> {
+        check_len(value.len(), MAX_LEAF_LEN)?;
+        // Owned content is turned into `&str` without checking it again.
+        if let Err(err) = std::str::from_utf8(value) {
+            return Err(RopeError::InvalidUtf8 {
+                offset: err.valid_up_to(),
+            });
         }
-        let last_index = value.len();
+        Ok(Self {
+            val: Content::Owned(value.to_vec()),
+            chars: count_chars(value),
+        })
+    }
+}
 
-        let mut vec = vec![0; MAX_LEAF_LEN];
-        vec[..last_index].copy_from_slice(value);
+fn check_len(len: usize, max: usize) -> RopeResult<()> {
+    if len > max {
+        return Err(RopeError::LeafTooLong { len, max });
+    }
+    Ok(())
+}
 
-        Self {
-            val: vec,
-            last_char_index: last_index,
+// Cuts a range of mapped bytes into pieces of at most `max` bytes, moving each cut back before a
+// char which doesn't fit. Only up to three continuation bytes are skipped, anything longer is
+// invalid anyway, so every piece is non-empty.
+pub(crate) fn mapped_pieces(
+    bytes: &[u8],
+    range: Range<usize>,
+    max: usize,
+) -> impl Iterator<Item = Range<usize>> + '_ {
+    let mut start = range.start;
+    std::iter::from_fn(move || {
+        if start >= range.end {
+            return None;
         }
-    }
+        let mut end = (start + max).min(range.end);
+        let min_end = end.saturating_sub(3).max(start + 1);
+        while end < range.end && end > min_end && is_continuation(bytes[end]) {
+            end -= 1;
+        }
+        let piece = start..end;
+        start = end;
+        Some(piece)
+    })
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::rope::node::Node;
-
-    use super::Leaf;
+    use super::{Leaf, Weight};
+    use crate::rope::error::RopeError;
 
     #[test]
     fn test_leaf_from_str() {
         let leaf = super::Leaf::from("Hello, world!");
 
         assert_eq!("Hello, world!".to_string(), leaf_to_str(&leaf));
-        assert_eq!(leaf.last_char_index, 13);
+        assert_eq!(leaf.get_char_bytes().len(), 13);
+        assert_eq!(leaf.heap_size(), 13);
+    }
+
+    #[test]
+    fn char_count_follows_edits() {
+        let mut leaf = super::Leaf::from("gęślą");
+        assert_eq!(5, leaf.weight());
+        assert!(!leaf.is_ascii());
+
+        leaf.prepend("zażółć ");
+        leaf.append(" jaźń");
+        leaf.insert_at(6, "!");
+        assert_eq!(18, leaf.weight());
+        assert_eq!("zażółć! gęślą jaźń", leaf_to_str(&leaf));
+
+        leaf.remove_range(2, 8);
+        assert_eq!(12, leaf.weight());
+        assert_eq!("zagęślą jaźń", leaf_to_str(&leaf));
+        assert_eq!(leaf_to_str(&leaf).chars().count(), leaf.weight());
+    }
+
+    #[test]
+    fn byte_positions_in_ascii_and_utf8_leaves() {
+        let ascii = super::Leaf::from("Hello");
+        let utf8 = super::Leaf::from("Zażółć");
+
+        assert!(ascii.is_ascii());
+        assert_eq!(3, ascii.byte_position_of_char_at(3));
+        assert_eq!(5, ascii.byte_position_of_char_at(5));
+        assert_eq!(4, utf8.byte_position_of_char_at(3));
+        assert_eq!(10, utf8.byte_position_of_char_at(6));
+    }
+
+    #[test]
+    fn merge_undersized_leaves() {
+        let small = super::Leaf::from("Hello, ");
+        let big = super::Leaf::from("a".repeat(super::MAX_LEAF_LEN - 7).as_str());
+        let too_big = super::Leaf::from("a".repeat(super::MAX_LEAF_LEN - 6).as_str());
+
+        let merged = super::Leaf::merge(&small, &big).unwrap();
+
+        assert_eq!(super::MAX_LEAF_LEN, merged.get_char_bytes().len());
+        assert!(leaf_to_str(&merged).starts_with("Hello, aaa"));
+        assert!(super::Leaf::merge(&small, &too_big).is_none());
+        assert!(super::Leaf::merge(&big, &big).is_none());
+    }
+
+    #[test]
+    fn leaf_grows_on_demand() {
+        let mut leaf = super::Leaf::from("world");
+
+        leaf.prepend("Hello, ");
+        leaf.append("!");
+
+        assert_eq!("Hello, world!".to_string(), leaf_to_str(&leaf));
+        assert!(leaf.heap_size() < super::MAX_LEAF_LEN);
     }
 
     #[test]
     fn remove_at_first_char() {
         let mut leaf = super::Leaf::from("Hello, world!");
 
-        let node = leaf.remove_char_at_node(0);
+        leaf.remove_range(0, 1);
 
-        if let Node::Leaf(node) = node {
-            assert_eq!("ello, world!".to_string(), leaf_to_str(&node));
-        }
+        assert_eq!("ello, world!".to_string(), leaf_to_str(&leaf));
+    }
+
+    #[test]
+    fn count_line_breaks() {
+        let leaf = super::Leaf::from("ą\nb\n\nc");
+
+        assert_eq!(3, leaf.newlines());
+        assert_eq!(1, leaf.newlines_before(2));
+        assert_eq!(2, leaf.char_after_newline(1));
+        assert_eq!(5, leaf.char_after_newline(3));
+    }
+
+    #[test]
+    fn mapped_leaf_is_copied_on_edit() {
+        let text = "zażółć ";
+        let mut map = memmap2::MmapMut::map_anon(16).unwrap();
+        map[..text.len()].copy_from_slice(text.as_bytes());
+        let map = std::sync::Arc::new(map.make_read_only().unwrap());
+
+        let mut leaf = super::Leaf::mapped(&map, 0..text.len());
+        let (left, right) = leaf.split_at_char(4);
+        assert!(leaf.is_mapped() && left.is_mapped() && right.is_mapped());
+        assert_eq!(0, leaf.heap_size());
+        assert_eq!(("zażó", "łć "), (left.as_str(), right.as_str()));
+
+        leaf.append("!");
+        assert!(!leaf.is_mapped());
+        assert_eq!("zażółć !", leaf_to_str(&leaf));
+        assert_eq!(text.as_bytes(), &map[..text.len()]);
+    }
+
+    #[test]
+    fn oversized_leaf_is_an_error() {
+        let bytes = vec![b'a'; super::MAX_LEAF_LEN + 1];
+
+        let err = super::Leaf::try_from(&bytes[..]).unwrap_err();
+
+        assert_eq!(
+            RopeError::LeafTooLong {
+                len: super::MAX_LEAF_LEN + 1,
+                max: super::MAX_LEAF_LEN
+            },
+            err
+        );
+        assert!(super::Leaf::try_from(&bytes[1..]).is_ok());
+    }
+
+    #[test]
+    fn invalid_utf8_leaf_is_an_error() {
+        let err = super::Leaf::try_from(&[b'a', b'b', 0xff, 0xfe][..]).unwrap_err();
+
+        assert_eq!(RopeError::InvalidUtf8 { offset: 2 }, err);
+        assert!(super::Leaf::try_from("aż".as_bytes()).is_ok());
+    }
+
+    #[test]
+    #[should_panic(expected = "Leaf cannot be longer than")]
+    fn oversized_leaf_panics() {
+        let _ = super::Leaf::from("a".repeat(super::MAX_LEAF_LEN + 1).as_str());
     }
 
     fn leaf_to_str(leaf: &Leaf) -> String {
-        std::str::from_utf8(&leaf.val[..leaf.last_char_index])
-            .unwrap()
-            .to_string()
+        std::str::from_utf8(&leaf.val).unwrap().to_string()
     }
 }
@@ -0,0 +1,71 @@
+use std::ops::{Add, AddAssign, Sub, SubAssign};
+
+use super::leaf::{count_chars, count_newlines};
+
+// Size of a piece of text in every unit the rope can be indexed by. Internal nodes keep these for
+// their left subtree, which is what makes conversions between the units O(log n).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Metrics {
+    pub chars: usize,
+    pub newlines: usize,
+    pub bytes: usize,
+    // UTF-16 code units, i.e. chars plus one for each char outside of the BMP.
+    pub utf16: usize,
+}
+
+impl Metrics {
+    // Metrics of valid UTF-8.
+    pub(crate) fn of(bytes: &[u8]) -> Self {
+        let chars = count_chars(bytes);
+        Self {
+            chars,
+            newlines: count_newlines(bytes),
+            bytes: bytes.len(),
+            utf16: chars + count_surrogate_pairs(bytes),
+        }
+    }
+}
+
+// Chars encoded in four bytes of UTF-8 take two UTF-16 code units. Their leading byte is the
+// only one starting with 0b11110.
+pub(crate) fn count_surrogate_pairs(bytes: &[u8]) -> usize {
+    bytes.iter().filter(|b| **b >= 0xF0).count()
+}
+
+impl Add for Metrics {
+    type Output = Metrics;
+
+    fn add(self, other: Metrics) -> Metrics {
+        Metrics {
+            chars: self.chars + other.chars,
+            newlines: self.newlines + other.newlines,
+            bytes: self.bytes + other.bytes,
+            utf16: self.utf16 + other.utf16,
+        }
+    }
+}
+
+impl Sub for Metrics {
+    type Output = Metrics;
+
+    fn sub(self, other: Metrics) -> Metrics {
+        Metrics {
+            chars: self.chars - other.chars,
+            newlines: self.newlines - other.newlines,
+            bytes: self.bytes - other.bytes,
+            utf16: self.utf16 - other.utf16,
+        }
+    }
+}
+
+impl AddAssign for Metrics {
+    fn add_assign(&mut self, other: Metrics) {
+        *self = *self + other;
+    }
+}
+
+impl SubAssign for Metrics {
+    fn sub_assign(&mut self, other: Metrics) {
+        *self = *self - other;
+    }
+}
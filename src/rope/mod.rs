@@ -1,8 +1,24 @@
+mod balance;
+pub mod changeset;
+mod content;
+mod debug;
+pub mod diff;
+pub mod error;
 mod func;
+mod grapheme;
+#[cfg(any(test, feature = "fuzzing"))]
+pub mod harness;
+pub mod history;
 mod internal;
-mod iter;
+pub mod iter;
 mod leaf;
+mod metrics;
 pub mod node;
+#[cfg(test)]
+mod proptests;
+pub mod regex;
 pub mod traverser;
 pub mod rope;
+pub mod search;
+pub mod slice;
 
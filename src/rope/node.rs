@@ -1,11 +1,21 @@
-use std::sync::Arc;
+use std::{
+    io::{self, Write},
+    ops::Range,
+    sync::Arc,
+};
 
 use super::{
+    balance::{from_parts, join},
     func::{Context, NodeResult},
     internal::Internal,
-    leaf::{Leaf, MAX_LEAF_LEN},
+    leaf::{Leaf, MAX_LEAF_LEN, MIN_LEAF_LEN},
+    metrics::Metrics,
 };
 
+// Allocation behind each Arc<Node>: strong and weak counters followed by the node.
+pub(crate) const ARC_NODE_SIZE: usize =
+    2 * std::mem::size_of::<usize>() + std::mem::size_of::<Node>();
+
 pub trait Weight {
     fn weight(&self) -> usize;
 }
@@ -25,11 +35,59 @@ impl Node {
         }
     }
 
+    // Total number of chars in this subtree.
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            Node::Leaf(leaf) => leaf.weight(),
+            Node::Internal(node) => node.weight + node.branches[1].len(),
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        match self {
+            Node::Leaf(_) => 0,
+            Node::Internal(internal) => internal.height,
+        }
+    }
+
+    // Restores balance of this node after height of one of its branches changed.
+    // Both branches have to be balanced already.
+    pub(crate) fn balance(&mut self) {
+        if let Node::Internal(node) = self {
+            node.update_height();
+            if node.balance_factor().abs() > 1 {
+                let [left, right] = node.branches.clone();
+                *self = join(left, right, node.left_metrics());
+            }
+        }
+    }
+
+    // Replaces an oversized mapped leaf with a balanced subtree of regular leaves, still reading
+    // from the map. Done before a leaf is edited, so that an edit copies at most one regular leaf.
+    pub(crate) fn cut_oversized(&mut self) {
+        let Node::Leaf(leaf) = self else {
+            return;
+        };
+        if !leaf.is_oversized() {
+            return;
+        }
+        let parts: Vec<(Arc<Node>, Metrics)> = leaf
+            .cut()
+            .into_iter()
+            .map(|leaf| {
+                let metrics = leaf.metrics();
+                (Arc::new(Node::from(leaf)), metrics)
+            })
+            .collect();
+        *self = Arc::unwrap_or_clone(from_parts(&parts).0);
+    }
+
     pub(crate) fn add_at<F>(&mut self, mut ctx: Context, f: F) -> NodeResult
     where
         F: Fn(Context, &mut Leaf) -> NodeResult,
     {
-        match self {
+        self.cut_oversized();
+        let result = match self {
             Node::Leaf(node) => {
                 match f(ctx, node) {
                     NodeResult::NewNode(new_node) => {
@@ -43,44 +101,267 @@ impl Node {
             Node::Internal(node) => {
                 let weight = node.weight;
                 if weight > ctx.index {
-                    let left = &mut node.branches[0];
-                    node.weight += ctx.buffer.len();
-                    Arc::make_mut(left).add_at(ctx, f)
+                    node.set_left_metrics(node.left_metrics() + Metrics::of(ctx.buffer.as_bytes()));
+                    Arc::make_mut(&mut node.branches[0]).add_at(ctx, f)
                 } else {
                     let right = &mut node.branches[1];
                     ctx.index -= weight;
                     Arc::make_mut(right).add_at(ctx, f)
                 }
             }
+        };
+        self.balance();
+        result
+    }
+
+    // Size of this subtree in every unit.
+    pub(crate) fn metrics(&self) -> Metrics {
+        match self {
+            Node::Leaf(leaf) => leaf.metrics(),
+            Node::Internal(node) => node.left_metrics() + node.branches[1].metrics(),
         }
     }
 
-    pub(crate) fn remove_at<F>(&mut self, mut ctx: Context, f: F) -> NodeResult
-    where
-        F: Fn(Context, &mut Leaf) -> NodeResult,
-    {
+    // Total number of line breaks in this subtree.
+    pub fn newlines(&self) -> usize {
         match self {
-            Node::Leaf(node) => {
-                match f(ctx, node) {
-                    NodeResult::NewNode(new_node) => {
-                        // TODO: Should if actually be std::mem::swap?
-                        *self = new_node;
-                        NodeResult::EditedInPlace
-                    }
-                    NodeResult::EditedInPlace => NodeResult::EditedInPlace,
+            Node::Leaf(leaf) => leaf.newlines(),
+            Node::Internal(node) => node.line_weight + node.branches[1].newlines(),
+        }
+    }
+
+    // Number of line breaks among the first `index` chars of this subtree.
+    pub(crate) fn newlines_before(&self, index: usize) -> usize {
+        match self {
+            Node::Leaf(leaf) => leaf.newlines_before(index),
+            Node::Internal(node) => {
+                if index <= node.weight {
+                    node.branches[0].newlines_before(index)
+                } else {
+                    node.line_weight + node.branches[1].newlines_before(index - node.weight)
+                }
+            }
+        }
+    }
+
+    // Char index right after the n-th (1-based) line break of this subtree.
+    pub(crate) fn char_after_newline(&self, n: usize) -> usize {
+        match self {
+            Node::Leaf(leaf) => leaf.char_after_newline(n),
+            Node::Internal(node) => {
+                if n <= node.line_weight {
+                    node.branches[0].char_after_newline(n)
+                } else {
+                    node.weight + node.branches[1].char_after_newline(n - node.line_weight)
+                }
+            }
+        }
+    }
+
+    // Byte offset of char at given index of this subtree.
+    pub(crate) fn char_to_byte(&self, index: usize) -> usize {
+        match self {
+            Node::Leaf(leaf) => leaf.byte_position_of_char_at(index),
+            Node::Internal(node) => {
+                if index <= node.weight {
+                    node.branches[0].char_to_byte(index)
+                } else {
+                    node.byte_weight + node.branches[1].char_to_byte(index - node.weight)
                 }
             }
+        }
+    }
+
+    // Char index at given byte offset, None if it falls inside of a char.
+    pub(crate) fn byte_to_char(&self, byte: usize) -> Option<usize> {
+        match self {
+            Node::Leaf(leaf) => leaf.byte_to_char(byte),
+            Node::Internal(node) => {
+                if byte <= node.byte_weight {
+                    node.branches[0].byte_to_char(byte)
+                } else {
+                    let index = node.branches[1].byte_to_char(byte - node.byte_weight)?;
+                    Some(node.weight + index)
+                }
+            }
+        }
+    }
+
+    // UTF-16 code units among the first `index` chars of this subtree.
+    pub(crate) fn char_to_utf16(&self, index: usize) -> usize {
+        match self {
+            Node::Leaf(leaf) => leaf.char_to_utf16(index),
+            Node::Internal(node) => {
+                if index <= node.weight {
+                    node.branches[0].char_to_utf16(index)
+                } else {
+                    node.utf16_weight + node.branches[1].char_to_utf16(index - node.weight)
+                }
+            }
+        }
+    }
+
+    // Char index at given UTF-16 offset, None if it splits a surrogate pair.
+    pub(crate) fn utf16_to_char(&self, units: usize) -> Option<usize> {
+        match self {
+            Node::Leaf(leaf) => leaf.utf16_to_char(units),
+            Node::Internal(node) => {
+                if units <= node.utf16_weight {
+                    node.branches[0].utf16_to_char(units)
+                } else {
+                    let index = node.branches[1].utf16_to_char(units - node.utf16_weight)?;
+                    Some(node.weight + index)
+                }
+            }
+        }
+    }
+
+    // Content of the leaf holding given byte offset, and the offset at which it starts.
+    // The end of the subtree belongs to the last leaf.
+    pub(crate) fn chunk_at_byte(&self, byte: usize) -> (&str, usize) {
+        match self {
+            Node::Leaf(leaf) => (leaf.as_str(), 0),
+            Node::Internal(node) => {
+                if byte < node.byte_weight {
+                    node.branches[0].chunk_at_byte(byte)
+                } else {
+                    let (chunk, start) = node.branches[1].chunk_at_byte(byte - node.byte_weight);
+                    (chunk, node.byte_weight + start)
+                }
+            }
+        }
+    }
+
+    // Splits this subtree in two at given char index, which has to be strictly inside of it.
+    // Only nodes along the path to the split point are rebuilt, every other subtree is shared
+    // and joined back into two balanced halves.
+    // Returns both halves and metrics of the left one.
+    pub(crate) fn split_at(self: &Arc<Self>, index: usize) -> (Arc<Node>, Arc<Node>, Metrics) {
+        match self.as_ref() {
+            Node::Leaf(leaf) => {
+                let (left, right) = leaf.split_at_char(index);
+                let metrics = left.metrics();
+                (Arc::new(left.into()), Arc::new(right.into()), metrics)
+            }
+            Node::Internal(node) => {
+                let [left, right] = &node.branches;
+                if index < node.weight {
+                    let (left_left, left_right, metrics) = left.split_at(index);
+                    let new_right = join(left_right, right.clone(), node.left_metrics() - metrics);
+                    (left_left, Arc::new(new_right), metrics)
+                } else if index > node.weight {
+                    let (right_left, right_right, metrics) = right.split_at(index - node.weight);
+                    let new_left = join(left.clone(), right_left, node.left_metrics());
+                    (
+                        Arc::new(new_left),
+                        right_right,
+                        node.left_metrics() + metrics,
+                    )
+                } else {
+                    (left.clone(), right.clone(), node.left_metrics())
+                }
+            }
+        }
+    }
+
+    // Replaces this subtree with a single leaf holding all of its content.
+    fn flatten(&mut self) {
+        let mut val = vec![];
+        self.write_to(&mut val)
+            .expect("Writing to a Vec cannot fail");
+        *self = Node::from(Leaf::from_bytes(&val));
+    }
+
+    // Bytes allocated on the heap by this subtree, not counting the node itself.
+    // Subtrees shared with other ropes are counted as if they were owned by this one.
+    pub fn heap_size(&self) -> usize {
+        match self {
+            Node::Leaf(leaf) => leaf.heap_size(),
+            Node::Internal(node) => node
+                .branches
+                .iter()
+                .map(|branch| ARC_NODE_SIZE + branch.heap_size())
+                .sum(),
+        }
+    }
+
+    // Writes content of this subtree straight from leaf buffers.
+    pub(crate) fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        match self {
+            Node::Leaf(leaf) => writer.write_all(leaf.get_char_bytes()),
+            Node::Internal(node) => {
+                node.branches[0].write_to(writer)?;
+                node.branches[1].write_to(writer)
+            }
+        }
+    }
+
+    // Collects leaves of this subtree in order along with their metrics.
+    pub(crate) fn collect_leaves(self: &Arc<Self>, out: &mut Vec<(Arc<Node>, Metrics)>) {
+        match self.as_ref() {
+            Node::Leaf(leaf) => {
+                if !leaf.is_empty() {
+                    out.push((self.clone(), leaf.metrics()));
+                }
+            }
+            Node::Internal(node) => {
+                node.branches[0].collect_leaves(out);
+                node.branches[1].collect_leaves(out);
+            }
+        }
+    }
+
+    // Removes chars in `range` from this subtree, `len` being the subtree's total char count.
+    // Subtrees that fall fully inside the range are dropped without being visited.
+    // Returns metrics of the removed text.
+    pub(crate) fn remove_range(&mut self, len: usize, range: Range<usize>) -> Metrics {
+        self.cut_oversized();
+        match self {
+            Node::Leaf(leaf) => leaf.remove_range(range.start, range.end),
             Node::Internal(node) => {
                 let weight = node.weight;
-                if weight > ctx.index {
-                    let left = &mut node.branches[0];
-                    node.weight -= 1; 
-                    Arc::make_mut(left).add_at(ctx, f)
+                let mut right_len = len - weight;
+                let mut removed_metrics = Metrics::default();
+
+                if range.start < weight {
+                    let end = range.end.min(weight);
+                    let removed = if range.start == 0 && end == weight {
+                        node.branches[0] = Arc::new(Node::from(""));
+                        node.left_metrics()
+                    } else {
+                        Arc::make_mut(&mut node.branches[0]).remove_range(weight, range.start..end)
+                    };
+                    node.set_left_metrics(node.left_metrics() - removed);
+                    removed_metrics += removed;
+                }
+
+                if range.end > weight {
+                    let start = range.start.max(weight) - weight;
+                    let end = range.end - weight;
+                    removed_metrics += if start == 0 && end == right_len {
+                        let removed = node.branches[1].metrics();
+                        node.branches[1] = Arc::new(Node::from(""));
+                        removed
+                    } else {
+                        Arc::make_mut(&mut node.branches[1]).remove_range(right_len, start..end)
+                    };
+                    right_len -= end - start;
+                }
+
+                // Don't keep internal nodes with an empty branch around, nor subtrees small
+                // enough to fit in a single leaf.
+                if node.weight == 0 {
+                    *self = node.take_branch(1);
+                } else if right_len == 0 {
+                    *self = node.take_branch(0);
+                } else if node.weight + right_len <= MIN_LEAF_LEN {
+                    self.flatten();
                 } else {
-                    let right = &mut node.branches[1];
-                    ctx.index -= weight;
-                    Arc::make_mut(right).add_at(ctx, f)
+                    // Rejoining merges leaves left undersized on both sides of the cut.
+                    let [left, right] = node.branches.clone();
+                    *self = join(left, right, node.left_metrics());
                 }
+                removed_metrics
             }
         }
     }
@@ -89,22 +370,17 @@ impl Node {
 impl From<&str> for Node {
     fn from(arg: &str) -> Self {
         if arg.len() > MAX_LEAF_LEN {
-            // Split at half OR split at max leaf len
-            //let (left, right) = arg.split_at(MAX_LEAF_LEN);
-            // THIS IS WRONG.
-
             let n = arg.chars().count();
-
-            let (left, right) = arg.split_at(n / 2);
+            let (mid, _) = arg.char_indices().nth(n / 2).unwrap();
+            let (left, right) = arg.split_at(mid);
 
             let left_node = Node::from(left);
             let right_node = Node::from(right);
-            let r = Node::Internal(Internal::with_branches_and_weight(
-                left_node,
-                right_node,
-                n / 2,
-            ));
-            r
+            join(
+                Arc::new(left_node),
+                Arc::new(right_node),
+                Metrics::of(left.as_bytes()),
+            )
         } else {
             Node::Leaf(Leaf::from(arg))
         }
@@ -0,0 +1,247 @@
+// Property tests driving the model-based harness with generated text and edits.
+
+use proptest::prelude::*;
+
+use super::{
+    changeset::ChangeSet,
+    diff::{DiffLine, Hunk},
+    harness::{apply, byte_index, check, run, Op},
+    rope::Rope,
+    traverser::Traverser,
+};
+
+// Short strings mixing ASCII, multi-byte chars and line breaks, with an occasional long one
+// spanning several leaves.
+fn text() -> impl Strategy<Value = String> {
+    prop_oneof![
+        8 => "[a-zA-Z ąęśćżźółń😀\n]{0,12}",
+        1 => "[a-z ąęś😀\n]{900,2000}",
+    ]
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        4 => (any::<usize>(), text()).prop_map(|(index, text)| Op::Insert(index, text)),
+        2 => text().prop_map(Op::Append),
+        4 => any::<usize>().prop_map(Op::RemoveAt),
+        2 => (any::<usize>(), any::<usize>()).prop_map(|(a, b)| Op::RemoveRange(a, b)),
+        1 => text().prop_map(Op::Concat),
+        1 => (any::<usize>(), any::<bool>()).prop_map(|(index, left)| Op::SplitAt(index, left)),
+        1 => Just(Op::Rebalance),
+        1 => (any::<usize>(), text()).prop_map(|(index, text)| Op::Clone(index, text)),
+    ]
+}
+
+// Change set for a text of given length, built from (kind, count, text) steps clamped to fit.
+fn changes(len: usize, steps: Vec<(u8, usize, String)>) -> ChangeSet {
+    let mut changes = ChangeSet::new(len);
+    let mut left = len;
+    for (kind, count, text) in steps {
+        let count = count % (left + 1);
+        changes = match kind % 3 {
+            0 => changes.retain(count),
+            1 => changes.delete(count),
+            _ => changes.insert(&text),
+        };
+        if kind % 3 != 2 {
+            left -= count;
+        }
+    }
+    changes
+}
+
+fn steps() -> impl Strategy<Value = Vec<(u8, usize, String)>> {
+    prop::collection::vec((any::<u8>(), 0..20usize, text()), 0..10)
+}
+
+// Applies unified diff hunks to the text, checking that their context and removed lines match.
+fn patch(text: &str, hunks: &[Hunk]) -> String {
+    let mut lines = text.split_inclusive('\n');
+    let mut patched = String::new();
+    let mut line = 0;
+    for hunk in hunks {
+        for _ in line..hunk.old_start {
+            patched.push_str(lines.next().expect("Hunk starts within the text"));
+        }
+        line = hunk.old_start + hunk.old_len;
+        for diff_line in &hunk.lines {
+            match diff_line {
+                DiffLine::Context(expected) => {
+                    let old = lines.next().expect("Context line exists");
+                    assert_eq!(expected, old);
+                    patched.push_str(old);
+                }
+                DiffLine::Removed(expected) => assert_eq!(Some(expected.as_str()), lines.next()),
+                DiffLine::Added(added) => patched.push_str(added),
+            }
+        }
+    }
+    patched.extend(lines);
+    patched
+}
+
+// Given number of cases, unless PROPTEST_CASES asks for another one.
+fn cases(default: u32) -> ProptestConfig {
+    let config = ProptestConfig::default();
+    if std::env::var_os("PROPTEST_CASES").is_some() {
+        return config;
+    }
+    ProptestConfig {
+        cases: default,
+        ..config
+    }
+}
+
+// These check the whole rope after every edit, so they run few cases to keep `cargo test` quick.
+// The fuzz target drives the same harness for as long as it's given.
+proptest! {
+    #![proptest_config(cases(16))]
+
+    #[test]
+    fn edits_match_string(initial in text(), ops in prop::collection::vec(op(), 0..40)) {
+        run(&initial, ops);
+    }
+
+    #[test]
+    fn edits_of_mapped_file_match_string(
+        initial in "[a-z ąęś😀\n]{900,2000}",
+        ops in prop::collection::vec(op(), 0..20),
+    ) {
+        let path = std::env::temp_dir()
+            .join(format!("editorus_edits_of_mapped_file_{:?}.txt", std::thread::current().id()));
+        std::fs::write(&path, &initial).unwrap();
+        let mut rope = Rope::from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut oracle = initial;
+        check(&rope, &oracle);
+        for op in ops {
+            apply(&mut rope, &mut oracle, op);
+            check(&rope, &oracle);
+        }
+    }
+
+    #[test]
+    fn removing_every_char_matches_string(
+        initial in "[a-zA-Z ąęśćżźółń😀\n]{0,300}",
+        seed in any::<usize>(),
+    ) {
+        let mut rope = Rope::from(initial.as_str());
+        let mut oracle = initial;
+
+        while !oracle.is_empty() {
+            apply(&mut rope, &mut oracle, Op::RemoveAt(seed));
+            check(&rope, &oracle);
+        }
+        assert_eq!(0, rope.len());
+    }
+}
+
+proptest! {
+    #![proptest_config(cases(100))]
+
+    #[test]
+    fn slices_match_string(initial in text(), a in any::<usize>(), b in any::<usize>()) {
+        let rope = Rope::from(initial.as_str());
+        let len = initial.chars().count();
+        let (a, b) = (a % (len + 1), b % (len + 1));
+        let (start, end) = (a.min(b), a.max(b));
+
+        let expected = &initial[byte_index(&initial, start)..byte_index(&initial, end)];
+        let slice = rope.slice(start..end);
+
+        prop_assert_eq!(expected, slice.to_string());
+        prop_assert_eq!(end - start, slice.len());
+        prop_assert!(slice.chars().rev().eq(expected.chars().rev()));
+    }
+
+    #[test]
+    fn change_sets_invert_and_compose(initial in text(), first in steps(), second in steps()) {
+        let original = Rope::from(initial.as_str());
+        let first = changes(original.len(), first);
+
+        let mut edited = original.clone();
+        first.apply(&mut edited);
+        prop_assert_eq!(first.len_after(), edited.len());
+        let second = changes(edited.len(), second);
+        let mut in_turn = edited.clone();
+        second.apply(&mut in_turn);
+
+        let mut at_once = original.clone();
+        first.compose(&second).apply(&mut at_once);
+        prop_assert_eq!(in_turn.value(), at_once.value());
+
+        first.invert(&original).apply(&mut edited);
+        prop_assert_eq!(initial, edited.value());
+    }
+
+    #[test]
+    fn hunks_turn_text_into_other(a in text(), b in text(), steps in steps(), context in 0..4usize) {
+        let old = Rope::from(a.as_str());
+        let mut edited = old.clone();
+        changes(old.len(), steps).apply(&mut edited);
+
+        for new in [edited, Rope::from(b.as_str())] {
+            prop_assert_eq!(new.value(), patch(&a, &old.diff_hunks(&new, context)));
+        }
+    }
+
+    #[test]
+    fn traverser_removals_match_string(
+        initial in "[a-z ąęś😀\n]{0,4000}",
+        cuts in prop::collection::vec((any::<usize>(), any::<usize>()), 0..10),
+    ) {
+        let mut expected: Vec<char> = initial.chars().collect();
+        let mut traverser = Traverser::new(Rope::from(initial.as_str()));
+        for (index, count) in cuts {
+            let index = index % (expected.len() + 1);
+            let count = count % (expected.len() - index + 1);
+            traverser.seek_char(index);
+            traverser.remove(count);
+            expected.drain(index..index + count);
+            prop_assert_eq!(index, traverser.index());
+        }
+
+        let rope = traverser.into_rope();
+        prop_assert_eq!(expected.into_iter().collect::<String>(), rope.value());
+        prop_assert_eq!(Ok(()), rope.check_invariants());
+    }
+
+    #[test]
+    fn comparison_matches_string(a in text(), b in text(), split in any::<usize>()) {
+        let split = a.char_indices().nth(split % (a.chars().count() + 1)).map_or(a.len(), |(i, _)| i);
+        let (left, right) = a.split_at(split);
+        let first = Rope::from(left).concat(Rope::from(right));
+        let second = Rope::from(b.as_str());
+        let whole = Rope::from(a.as_str());
+
+        prop_assert_eq!(a.cmp(&b), first.cmp(&second));
+        prop_assert_eq!(a == b, first == second);
+        prop_assert!(first == a && first == whole);
+    }
+
+    #[test]
+    fn diff_turns_rope_into_edited_clone(initial in text(), steps in steps(), other in text()) {
+        let original = Rope::from(initial.as_str());
+        let mut edited = original.clone();
+        changes(original.len(), steps).apply(&mut edited);
+
+        for target in [edited, Rope::from(other.as_str())] {
+            let mut rope = original.clone();
+            original.diff(&target).apply(&mut rope);
+            prop_assert_eq!(target.value(), rope.value());
+        }
+    }
+}
+
+#[test]
+fn delete_in_line_with_utf8_chars() {
+    let mut rope = Rope::from("zażółć\ngęślą jaźń\n");
+    let mut oracle = String::from("zażółć\ngęślą jaźń\n");
+
+    for index in [3, 3, 9, 6, 0] {
+        apply(&mut rope, &mut oracle, Op::RemoveAt(index));
+        check(&rope, &oracle);
+    }
+    assert_eq!("ażć\ngśl jaźń\n", rope.value());
+}
@@ -0,0 +1,291 @@
+use std::ops::Range;
+
+use regex_automata::{
+    hybrid::{
+        dfa::{Cache, DFA},
+        BuildError,
+    },
+    nfa::thompson::{self, WhichCaptures},
+    util::{start, syntax},
+    Anchored, MatchKind,
+};
+
+use super::{
+    error::{RopeError, RopeResult},
+    slice::RopeSlice,
+};
+
+// Regular expression searched over the stream of bytes of a rope, chunk after chunk, with lazy
+// DFAs. A forward DFA finds where the leftmost match ends, a reverse one walks back from there to
+// find where it starts. Neither needs the text in one piece.
+//
+// `^` and `$` match at line breaks of the rope (multi-line mode), which can be turned off with
+// `(?-m)`. When searching a slice, anchors look at the text around it, so `^` at the start of a
+// slice matches only if a line really starts there. Unicode word boundaries (`\b`) aren't
+// supported by lazy DFAs, ASCII ones (`(?-u:\b)`) are.
+#[derive(Clone, Debug)]
+pub struct Regex {
+    forward: DFA,
+    reverse: DFA,
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> RopeResult<Self> {
+        let invalid = |err: BuildError| RopeError::InvalidRegex(err.to_string());
+        let syntax = syntax::Config::new().multi_line(true);
+        let forward = DFA::builder()
+            .syntax(syntax)
+            .build(pattern)
+            .map_err(invalid)?;
+        let reverse = DFA::builder()
+            .syntax(syntax)
+            .thompson(
+                thompson::Config::new()
+                    .reverse(true)
+                    .which_captures(WhichCaptures::None),
+            )
+            .configure(DFA::config().match_kind(MatchKind::All))
+            .build(pattern)
+            .map_err(invalid)?;
+
+        Ok(Self { forward, reverse })
+    }
+
+    fn caches(&self) -> (Cache, Cache) {
+        (self.forward.create_cache(), self.reverse.create_cache())
+    }
+
+    // Leftmost-first match in the slice starting at char `from`. Char indices are relative to
+    // the slice.
+    fn find_at(
+        &self,
+        caches: &mut (Cache, Cache),
+        slice: RopeSlice<'_>,
+        from: usize,
+    ) -> Option<Range<usize>> {
+        let haystack = slice.slice(from..);
+        let end = self.match_end(&mut caches.0, haystack)?;
+        let start = self.match_start(&mut caches.1, haystack.slice(..end));
+        Some(from + start..from + end)
+    }
+
+    // Matches are reported one byte late, so the state reached after a byte tells whether a match
+    // ends right before it. Past the end of the slice, the byte following it is fed instead of
+    // the end of input, so that `$` sees the rest of the rope.
+    fn match_end(&self, cache: &mut Cache, haystack: RopeSlice<'_>) -> Option<usize> {
+        let config = start::Config::new()
+            .anchored(Anchored::No)
+            .look_behind(haystack.byte_before());
+        let mut state = self.forward.start_state(cache, &config).expect(GIVE_UP);
+        let mut bytes = haystack.bytes();
+        let mut chars = 0;
+        let mut end = None;
+
+        loop {
+            let byte = bytes.next();
+            state = match byte.or_else(|| haystack.byte_after()) {
+                Some(byte) => self.forward.next_state(cache, state, byte),
+                None => self.forward.next_eoi_state(cache, state),
+            }
+            .expect(GIVE_UP);
+
+            if state.is_match() {
+                end = Some(chars);
+            } else if state.is_dead() {
+                return end;
+            }
+            match byte {
+                Some(byte) if is_char_start(byte) => chars += 1,
+                Some(_) => {}
+                None => return end,
+            }
+        }
+    }
+
+    // Walks back from the end of the match, the earliest position where the reverse DFA matches
+    // is where the match starts.
+    fn match_start(&self, cache: &mut Cache, haystack: RopeSlice<'_>) -> usize {
+        let config = start::Config::new()
+            .anchored(Anchored::Yes)
+            .look_behind(haystack.byte_after());
+        let mut state = self.reverse.start_state(cache, &config).expect(GIVE_UP);
+        let mut bytes = haystack.bytes().rev();
+        let mut chars = 0;
+        let mut start = haystack.len();
+
+        loop {
+            let byte = bytes.next();
+            state = match byte.or_else(|| haystack.byte_before()) {
+                Some(byte) => self.reverse.next_state(cache, state, byte),
+                None => self.reverse.next_eoi_state(cache, state),
+            }
+            .expect(GIVE_UP);
+
+            if state.is_match() {
+                start = haystack.len() - chars;
+            } else if state.is_dead() {
+                return start;
+            }
+            match byte {
+                Some(byte) if is_char_start(byte) => chars += 1,
+                Some(_) => {}
+                None => return start,
+            }
+        }
+    }
+}
+
+// Lazy DFAs give up only when configured with quit bytes or a minimum cache efficiency, and
+// neither is used here.
+const GIVE_UP: &str = "lazy DFA gave up searching";
+
+fn is_char_start(byte: u8) -> bool {
+    byte & 0xC0 != 0x80
+}
+
+pub(crate) fn find(regex: &Regex, slice: RopeSlice<'_>) -> Option<Range<usize>> {
+    regex.find_at(&mut regex.caches(), slice, 0)
+}
+
+// Successive non-overlapping matches. An empty match right where the previous one ended is
+// skipped, like in the `regex` crate.
+#[derive(Debug)]
+pub struct RegexIter<'a, 'r> {
+    regex: &'r Regex,
+    caches: (Cache, Cache),
+    slice: RopeSlice<'a>,
+    position: usize,
+    last_end: Option<usize>,
+}
+
+impl<'a, 'r> RegexIter<'a, 'r> {
+    pub(crate) fn new(regex: &'r Regex, slice: RopeSlice<'a>) -> Self {
+        Self {
+            regex,
+            caches: regex.caches(),
+            slice,
+            position: 0,
+            last_end: None,
+        }
+    }
+}
+
+impl Iterator for RegexIter<'_, '_> {
+    type Item = Range<usize>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.position <= self.slice.len() {
+            let found = self
+                .regex
+                .find_at(&mut self.caches, self.slice, self.position)?;
+            if found.is_empty() && Some(found.end) == self.last_end {
+                self.position = found.end + 1;
+                continue;
+            }
+            self.position = found.end;
+            self.last_end = Some(found.end);
+            return Some(found);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Regex;
+    use crate::rope::{error::RopeError, rope::Rope};
+
+    fn find_all(rope: &Rope, pattern: &str) -> Vec<std::ops::Range<usize>> {
+        rope.find_regex_iter(&Regex::new(pattern).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn find_leftmost_first() {
+        let rope = Rope::from("foo123 bar45 baz");
+        let regex = Regex::new(r"[a-z]+\d+").unwrap();
+
+        assert_eq!(Some(0..6), rope.find_regex(&regex));
+        assert_eq!(vec![0..6, 7..12], find_all(&rope, r"[a-z]+\d+"));
+        assert_eq!(vec![0..3], find_all(&rope, "foo|foo123"));
+        assert_eq!(None, rope.find_regex(&Regex::new("qux").unwrap()));
+    }
+
+    #[test]
+    fn returns_char_ranges() {
+        let rope = Rope::from("zażółć gęślą jaźń");
+
+        assert_eq!(
+            vec![2..6, 8..12, 15..17],
+            find_all(&rope, r"[ąćęłńóśźż]+\w*")
+        );
+        assert_eq!(vec![7..12], find_all(&rope, "g.ś.ą"));
+    }
+
+    #[test]
+    fn matches_across_leaf_boundaries() {
+        let text = "lorem ipsum dolor sit amet\n".repeat(100);
+        let rope = Rope::from_reader(text.as_bytes()).unwrap();
+        assert!(rope.chunks().count() > 1);
+
+        let matches = find_all(&rope, r"amet\nlorem");
+        assert_eq!(99, matches.len());
+        for (idx, range) in matches.iter().enumerate() {
+            assert_eq!(idx * 27 + 22..idx * 27 + 32, *range);
+        }
+    }
+
+    #[test]
+    fn line_anchors() {
+        let rope = Rope::from("one two\ntwo one\none");
+
+        assert_eq!(vec![0..3, 16..19], find_all(&rope, "^one"));
+        assert_eq!(vec![4..7, 12..15, 16..19], find_all(&rope, r"\w+$"));
+        assert_eq!(vec![0..3], find_all(&rope, "(?-m)^one"));
+        assert_eq!(vec![0..0, 8..8, 16..16], find_all(&rope, "^"));
+    }
+
+    #[test]
+    fn anchors_in_slice_see_surrounding_text() {
+        let rope = Rope::from("one two\ntwo one\none");
+        let regex = Regex::new("^t..$").unwrap();
+
+        assert_eq!(None, rope.slice(4..7).find_regex(&regex));
+        assert_eq!(None, rope.slice(8..11).find_regex(&regex));
+
+        let line = rope.slice(rope.line_to_char(1)..rope.line_to_char(2));
+        let regex = Regex::new("^two|one$").unwrap();
+        assert_eq!(
+            vec![0..3, 4..7],
+            line.find_regex_iter(&regex).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn empty_matches() {
+        let rope = Rope::from("baaab");
+
+        assert_eq!(vec![0..0, 1..4, 5..5], find_all(&rope, "a*"));
+        assert_eq!(vec![0..0, 1..1, 2..2], find_all(&Rope::from("żó"), ""));
+    }
+
+    #[test]
+    fn replace_all() {
+        let mut rope = Rope::from("let x = 1;\nlet yy = 22;\n");
+        let regex = Regex::new(r"\d+").unwrap();
+
+        assert_eq!(2, rope.replace_regex(&regex, "N"));
+        assert_eq!("let x = N;\nlet yy = N;\n", rope.value());
+        assert_eq!(0, rope.replace_regex(&regex, "N"));
+    }
+
+    #[test]
+    fn invalid_pattern() {
+        assert!(matches!(
+            Regex::new("(unclosed"),
+            Err(RopeError::InvalidRegex(_))
+        ));
+        assert!(Regex::new(r"\b").is_err());
+        assert!(Regex::new(r"(?-u:\b)").is_ok());
+    }
+}
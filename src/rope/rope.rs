@@ -1,10 +1,28 @@
-use std::sync::Arc;
+use std::{
+    cmp::Ordering,
+    fmt,
+    hash::{Hash, Hasher},
+    io::{self, Read, Write},
+    ops::{Range, RangeBounds},
+    path::Path,
+    sync::Arc,
+};
 
 use super::{
-    func::{insert, remove_at, Context},
-    internal::Internal,
-    iter::LeafIterator,
-    node::Node,
+    balance::{from_parts, join},
+    changeset::ChangeSet,
+    content::map_file,
+    debug::{check_node, write_graphviz, write_indented},
+    diff::{diff, hunks, skip_shared, Hunk},
+    error::{InvariantError, RopeError, RopeResult, Violation},
+    func::{insert, Context},
+    grapheme::{next_boundary, prev_boundary},
+    iter::{Bytes, Chars, Chunks},
+    leaf::{mapped_pieces, Leaf, MAX_LEAF_LEN, MAX_MAPPED_LEAF_LEN},
+    node::{Node, ARC_NODE_SIZE},
+    regex::{Regex, RegexIter},
+    search::{FindIter, MultiFindIter, MultiPattern, Pattern},
+    slice::{resolve_range, RopeSlice},
 };
 
 // Rope data structure
@@ -14,20 +32,49 @@ pub struct Rope {
     len: usize,
 }
 
-// TODO: Remove all panics
+// Every method which can fail has a `try_` variant returning `RopeError`. The plain ones panic.
 impl Rope {
     pub fn new() -> Self {
         Self {
-            root: Arc::new(Node::Internal(Internal::new())),
+            root: Arc::new(Node::from("")),
             len: 0,
         }
     }
 
-    pub fn leaf_iter(&self) -> LeafIterator {
-        LeafIterator::new(&self.root)
+    pub fn chunks(&self) -> Chunks<'_> {
+        Chunks::new(&self.root, 0..self.len)
+    }
+
+    pub fn chars(&self) -> Chars<'_> {
+        Chars::new(self.chunks())
+    }
+
+    pub fn bytes(&self) -> Bytes<'_> {
+        Bytes::new(self.chunks())
+    }
+
+    // Chars starting at given char index.
+    pub fn chars_at(&self, index: usize) -> Chars<'_> {
+        self.try_chars_at(index)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn try_chars_at(&self, index: usize) -> RopeResult<Chars<'_>> {
+        self.check_index(index)?;
+        Ok(Chars::new(Chunks::new(&self.root, index..self.len)))
+    }
+
+    fn check_index(&self, index: usize) -> RopeResult<()> {
+        if index > self.len {
+            return Err(RopeError::IndexOutOfBounds {
+                index,
+                len: self.len,
+            });
+        }
+        Ok(())
     }
 
-    fn with_root(node: Node, len: usize) -> Rope {
+    pub(crate) fn with_root(node: Node, len: usize) -> Rope {
         Rope {
             len,
             root: Arc::new(node),
@@ -37,9 +84,7 @@ impl Rope {
     // TODO: Do we really need to clone in this method?
     // Don't clone!!!
     pub fn value(&self) -> String {
-        LeafIterator::new(&self.root)
-            .map(|leaf| leaf.to_string())
-            .collect()
+        self.chunks().collect()
     }
 
     pub fn len(&self) -> usize {
@@ -51,44 +96,351 @@ impl Rope {
     }
 
     pub fn insert(&mut self, index: usize, arg: &str) {
+        self.try_insert(index, arg)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn try_insert(&mut self, index: usize, arg: &str) -> RopeResult<()> {
+        self.check_index(index)?;
         let node = Arc::make_mut(&mut self.root);
-        if index > self.len {
-            panic!("Index out of bounds");
-        }
         let context = Context::new(index, arg);
         node.add_at(context, insert);
         self.len += arg.chars().count();
+        Ok(())
     }
 
     pub fn concat(self, other: Rope) -> Rope {
-        let mut new_internal = Internal::new();
-        new_internal.branches[0] = self.root;
-        new_internal.branches[1] = other.root;
-        new_internal.weight = self.len;
-        Rope::with_root(Node::from(new_internal), self.len + other.len)
+        if self.len == 0 {
+            return other;
+        }
+        if other.len == 0 {
+            return self;
+        }
+
+        let metrics = self.root.metrics();
+        let root = join(self.root, other.root, metrics);
+        Rope::with_root(root, self.len + other.len)
     }
 
     pub fn remove_at(&mut self, index: usize) {
-        if self.len() == 0 {
-            // Do nothing
-            return;
+        self.try_remove_at(index)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn try_remove_at(&mut self, index: usize) -> RopeResult<()> {
+        if index >= self.len() {
+            return Err(RopeError::IndexOutOfBounds {
+                index,
+                len: self.len,
+            });
+        }
+
+        self.try_remove_range(index..index + 1)
+    }
+
+    pub fn remove_range(&mut self, range: Range<usize>) {
+        self.try_remove_range(range)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn try_remove_range(&mut self, range: Range<usize>) -> RopeResult<()> {
+        if range.start > range.end {
+            return Err(RopeError::InvalidRange {
+                start: range.start,
+                end: range.end,
+            });
         }
+        self.check_index(range.end)?;
 
-        if index > self.len() {
-            panic!("Index out of bounds");
+        if range.is_empty() {
+            return Ok(());
+        }
+
+        if range.len() == self.len() {
+            self.root = Arc::new(Node::from(""));
+            self.len = 0;
+            return Ok(());
         }
 
         let node = Arc::make_mut(&mut self.root);
-        let context = Context::new(index, "");
-        node.remove_at(context, remove_at);
-        self.len -= 1;
+        node.remove_range(self.len, range.clone());
+        self.len -= range.len();
+        Ok(())
+    }
+
+    // Lines are separated by '\n', so an empty rope still has one (empty) line.
+    pub fn line_count(&self) -> usize {
+        self.root.newlines() + 1
+    }
+
+    // Returns char index at which given line starts.
+    // Passing `line_count()` returns `len()`, so `line_to_char(n + 1)` always bounds line `n`.
+    pub fn line_to_char(&self, line: usize) -> usize {
+        self.try_line_to_char(line)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn try_line_to_char(&self, line: usize) -> RopeResult<usize> {
+        let line_count = self.line_count();
+        if line > line_count {
+            return Err(RopeError::IndexOutOfBounds {
+                index: line,
+                len: line_count,
+            });
+        }
+
+        Ok(if line == 0 {
+            0
+        } else if line == line_count {
+            self.len
+        } else {
+            self.root.char_after_newline(line)
+        })
+    }
+
+    // Returns index of the line containing char at given index.
+    pub fn char_to_line(&self, index: usize) -> usize {
+        self.try_char_to_line(index)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn try_char_to_line(&self, index: usize) -> RopeResult<usize> {
+        self.check_index(index)?;
+        Ok(self.root.newlines_before(index))
+    }
+
+    // Length of the content encoded as UTF-8.
+    pub fn len_bytes(&self) -> usize {
+        self.root.metrics().bytes
+    }
+
+    // Length of the content encoded as UTF-16, which is how language servers count columns.
+    pub fn len_utf16(&self) -> usize {
+        self.root.metrics().utf16
+    }
+
+    // Returns byte offset at which char at given index starts.
+    pub fn char_to_byte(&self, index: usize) -> usize {
+        self.try_char_to_byte(index)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn try_char_to_byte(&self, index: usize) -> RopeResult<usize> {
+        self.check_index(index)?;
+        Ok(self.root.char_to_byte(index))
+    }
+
+    // Returns index of the char starting at given byte offset.
+    pub fn byte_to_char(&self, byte: usize) -> usize {
+        self.try_byte_to_char(byte)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn try_byte_to_char(&self, byte: usize) -> RopeResult<usize> {
+        let len = self.len_bytes();
+        if byte > len {
+            return Err(RopeError::IndexOutOfBounds { index: byte, len });
+        }
+        self.root
+            .byte_to_char(byte)
+            .ok_or(RopeError::NotCharBoundary(byte))
+    }
+
+    // Returns UTF-16 offset at which char at given index starts.
+    pub fn char_to_utf16(&self, index: usize) -> usize {
+        self.try_char_to_utf16(index)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn try_char_to_utf16(&self, index: usize) -> RopeResult<usize> {
+        self.check_index(index)?;
+        Ok(self.root.char_to_utf16(index))
+    }
+
+    // Returns index of the char starting at given UTF-16 offset.
+    pub fn utf16_to_char(&self, units: usize) -> usize {
+        self.try_utf16_to_char(units)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn try_utf16_to_char(&self, units: usize) -> RopeResult<usize> {
+        let len = self.len_utf16();
+        if units > len {
+            return Err(RopeError::IndexOutOfBounds { index: units, len });
+        }
+        self.root
+            .utf16_to_char(units)
+            .ok_or(RopeError::NotUtf16Boundary(units))
     }
 
+    // Char index of the next grapheme cluster boundary after given index, so that moving there
+    // never splits an emoji or a char from its combining marks. Returns `len()` at the end.
+    pub fn next_grapheme(&self, index: usize) -> usize {
+        self.try_next_grapheme(index)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn try_next_grapheme(&self, index: usize) -> RopeResult<usize> {
+        self.check_index(index)?;
+        if index == self.len {
+            return Ok(index);
+        }
+        let byte = self.root.char_to_byte(index);
+        let boundary = next_boundary(&self.root, byte, self.len_bytes());
+        self.try_byte_to_char(boundary)
+    }
+
+    // Char index of the previous grapheme cluster boundary before given index. Returns 0 at the
+    // beginning.
+    pub fn prev_grapheme(&self, index: usize) -> usize {
+        self.try_prev_grapheme(index)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn try_prev_grapheme(&self, index: usize) -> RopeResult<usize> {
+        self.check_index(index)?;
+        if index == 0 {
+            return Ok(0);
+        }
+        let byte = self.root.char_to_byte(index);
+        let boundary = prev_boundary(&self.root, byte, self.len_bytes());
+        self.try_byte_to_char(boundary)
+    }
+
+    // Returns given line, without the trailing line break.
+    pub fn line(&self, line: usize) -> RopeSlice<'_> {
+        self.try_line(line).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn try_line(&self, line: usize) -> RopeResult<RopeSlice<'_>> {
+        if line >= self.line_count() {
+            return Err(RopeError::IndexOutOfBounds {
+                index: line,
+                len: self.line_count(),
+            });
+        }
+
+        let start = self.try_line_to_char(line)?;
+        let mut end = self.try_line_to_char(line + 1)?;
+        if line + 1 < self.line_count() {
+            end -= 1;
+        }
+        Ok(RopeSlice::new(&self.root, start..end))
+    }
+
+    // Returns given line without its line ending, which may be "\r\n" as well, like `str::lines`.
+    pub fn line_without_ending(&self, line: usize) -> RopeSlice<'_> {
+        let slice = self.line(line);
+        if line + 1 < self.line_count() && slice.chars().next_back() == Some('\r') {
+            return slice.slice(..slice.len() - 1);
+        }
+        slice
+    }
+
+    pub fn slice<R: RangeBounds<usize>>(&self, range: R) -> RopeSlice<'_> {
+        self.try_slice(range)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn try_slice<R: RangeBounds<usize>>(&self, range: R) -> RopeResult<RopeSlice<'_>> {
+        let range = resolve_range(range, self.len)?;
+        Ok(RopeSlice::new(&self.root, range))
+    }
+
+    pub fn find<P: Into<Pattern>>(&self, pattern: P) -> Option<usize> {
+        self.slice(..).find(pattern)
+    }
+
+    pub fn rfind<P: Into<Pattern>>(&self, pattern: P) -> Option<usize> {
+        self.slice(..).rfind(pattern)
+    }
+
+    pub fn find_iter<P: Into<Pattern>>(&self, pattern: P) -> FindIter<'_> {
+        self.slice(..).find_iter(pattern)
+    }
+
+    pub fn find_all(&self, patterns: MultiPattern) -> MultiFindIter<'_> {
+        self.slice(..).find_all(patterns)
+    }
+
+    pub fn find_regex(&self, regex: &Regex) -> Option<Range<usize>> {
+        self.slice(..).find_regex(regex)
+    }
+
+    pub fn find_regex_iter<'r>(&self, regex: &'r Regex) -> RegexIter<'_, 'r> {
+        self.slice(..).find_regex_iter(regex)
+    }
+
+    // Replaces every match of the regex with given text. Returns number of replaced matches.
+    pub fn replace_regex(&mut self, regex: &Regex, replacement: &str) -> usize {
+        let matches: Vec<Range<usize>> = self.find_regex_iter(regex).collect();
+        // From the back, so that ranges still to be replaced don't move.
+        for range in matches.iter().rev() {
+            self.remove_range(range.clone());
+            self.insert(range.start, replacement);
+        }
+        matches.len()
+    }
+
+    // Changes turning this rope into the other one. Subtrees both ropes share are skipped without
+    // comparing them, the rest is diffed line by line.
+    pub fn diff(&self, other: &Rope) -> ChangeSet {
+        diff(self, other)
+    }
+
+    // Differing lines grouped into unified diff hunks with `context` unchanged lines around them.
+    pub fn diff_hunks(&self, other: &Rope, context: usize) -> Vec<Hunk> {
+        hunks(self, other, context)
+    }
+
+    // Bytes allocated on the heap by the whole tree, including content of every leaf.
+    pub fn heap_size(&self) -> usize {
+        ARC_NODE_SIZE + self.root.heap_size()
+    }
+
+    // Streams content to the writer chunk by chunk, without building a `String`.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.root.write_to(writer)
+    }
+
+    // Edits keep the rope balanced already. This only packs it into a tree of minimal height,
+    // sharing all of the existing leaves.
     pub fn rebalance(&mut self) {
-        // TODO: Find better way of rebalancing this?
-        // TODO: Find a way to determine a better rebalancing timing
-        let collected = Node::from(self.value().as_str());
-        self.root = Arc::new(collected);
+        let mut leaves = vec![];
+        self.root.collect_leaves(&mut leaves);
+        let (root, _) = from_parts(&leaves);
+        self.root = root;
+    }
+
+    // Checks every node of the tree against its content, reporting the first broken invariant
+    // along with the path to the offending node.
+    pub fn check_invariants(&self) -> Result<(), InvariantError> {
+        let (metrics, _) = check_node(&self.root, &mut String::new())?;
+        let len = metrics.chars;
+        if len != self.len {
+            return Err(InvariantError {
+                path: String::new(),
+                violation: Violation::StaleLength {
+                    expected: len,
+                    found: self.len,
+                },
+            });
+        }
+        Ok(())
+    }
+
+    // Human readable dump of the tree, one node per line, for tests and bug reports.
+    pub fn debug_tree(&self) -> String {
+        let mut out = String::new();
+        write_indented(&mut out, &self.root, 0);
+        out
+    }
+
+    // Same as `debug_tree`, but in Graphviz (dot) format.
+    pub fn debug_tree_graphviz(&self) -> String {
+        let mut out = String::new();
+        write_graphviz(&mut out, &self.root);
+        out
     }
 
     // TODO: Remove. Used only for benching traversal. Add cfg?
@@ -97,18 +449,126 @@ impl Rope {
     }
 
     pub fn split_at(&self, index: usize) -> (Rope, Rope) {
+        self.try_split_at(index)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn try_split_at(&self, index: usize) -> RopeResult<(Rope, Rope)> {
+        self.check_index(index)?;
         if index == 0 {
-            return (Rope::new(), self.clone());
+            return Ok((Rope::new(), self.clone()));
         }
-        if index > self.len {
-            panic!("Index out of bounds");
+        if index == self.len {
+            return Ok((self.clone(), Rope::new()));
+        }
+
+        let (left, right, _) = self.root.split_at(index);
+        Ok((
+            Rope {
+                root: left,
+                len: index,
+            },
+            Rope {
+                root: right,
+                len: self.len - index,
+            },
+        ))
+    }
+}
+
+impl Rope {
+    // Builds a rope leaf by leaf, so the content never has to fit in a single `String`.
+    // Leaves are filled up to MAX_LEAF_LEN bytes, cut at char boundaries, and then joined
+    // bottom-up into a balanced tree. Invalid UTF-8 is reported as `InvalidData` error wrapping
+    // `RopeError::InvalidUtf8` with the offset of the first invalid byte.
+    pub fn from_reader<R: Read>(mut reader: R) -> io::Result<Rope> {
+        let mut parts = vec![];
+        let mut buffer = vec![0; MAX_LEAF_LEN];
+        let mut filled = 0;
+        let mut offset = 0;
+
+        loop {
+            let read = match reader.read(&mut buffer[filled..]) {
+                Ok(read) => read,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            };
+            filled += read;
+            let eof = read == 0;
+
+            if !eof && filled < buffer.len() {
+                continue;
+            }
+            if filled == 0 {
+                break;
+            }
+
+            let valid = match std::str::from_utf8(&buffer[..filled]) {
+                Ok(_) => filled,
+                // Codepoint cut by the end of the buffer, it's going to be completed by next read.
+                Err(err) if err.error_len().is_none() && !eof => err.valid_up_to(),
+                Err(err) => {
+                    let err = RopeError::InvalidUtf8 {
+                        offset: offset + err.valid_up_to(),
+                    };
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+                }
+            };
+
+            let leaf = Leaf::from_bytes(&buffer[..valid]);
+            let metrics = leaf.metrics();
+            parts.push((Arc::new(Node::from(leaf)), metrics));
+
+            buffer.copy_within(valid..filled, 0);
+            filled -= valid;
+            offset += valid;
+
+            if eof {
+                break;
+            }
         }
-        let value = self.value();
 
-        let u_idx: usize = value.chars().take(index).map(|c| c.len_utf8()).sum();
+        if parts.is_empty() {
+            return Ok(Rope::new());
+        }
+        let (root, metrics) = from_parts(&parts);
+        Ok(Rope {
+            root,
+            len: metrics.chars,
+        })
+    }
+
+    // Builds a rope over a memory map of a snapshot of the file, see `map_file`. Memory use stays
+    // bounded: leaves cover up to MAX_MAPPED_LEAF_LEN bytes, so the tree itself is around a
+    // thousandth of the file size, and only leaves which are edited get copied out of the map.
+    // Opening is not instant though. Nothing is decoded lazily: before this returns, the file is
+    // copied to the snapshot and the whole snapshot is read once to validate UTF-8 and count
+    // chars and lines of every leaf, which takes time proportional to the file size.
+    // Errors are reported the same way as by `from_reader`.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Rope> {
+        let Some(map) = map_file(path.as_ref())? else {
+            return Ok(Rope::new());
+        };
+
+        let mut parts = vec![];
+        for piece in mapped_pieces(&map, 0..map.len(), MAX_MAPPED_LEAF_LEN) {
+            if let Err(err) = std::str::from_utf8(&map[piece.clone()]) {
+                let err = RopeError::InvalidUtf8 {
+                    offset: piece.start + err.valid_up_to(),
+                };
+                return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+            }
+
+            let leaf = Leaf::mapped(&map, piece);
+            let metrics = leaf.metrics();
+            parts.push((Arc::new(Node::from(leaf)), metrics));
+        }
 
-        let (left, right) = value.split_at(u_idx);
-        (Rope::from(left), Rope::from(right))
+        let (root, metrics) = from_parts(&parts);
+        Ok(Rope {
+            root,
+            len: metrics.chars,
+        })
     }
 }
 
@@ -121,10 +581,122 @@ impl From<&str> for Rope {
     }
 }
 
+impl fmt::Display for Rope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for chunk in self.chunks() {
+            f.write_str(chunk)?;
+        }
+        Ok(())
+    }
+}
+
+impl PartialEq for Rope {
+    fn eq(&self, other: &Rope) -> bool {
+        self.len == other.len && self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Rope {}
+
+impl PartialOrd for Rope {
+    fn partial_cmp(&self, other: &Rope) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Subtrees both ropes start with are skipped without looking at them, so comparing a rope with
+// its edited clone only reads leaves from the first edit on.
+impl Ord for Rope {
+    fn cmp(&self, other: &Rope) -> Ordering {
+        let shared = skip_shared(&self.root, &other.root, 0);
+        self.slice(shared..).compare(&other.slice(shared..))
+    }
+}
+
+impl Hash for Rope {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.slice(..).hash(state);
+    }
+}
+
+impl PartialEq<RopeSlice<'_>> for Rope {
+    fn eq(&self, other: &RopeSlice<'_>) -> bool {
+        self.slice(..) == *other
+    }
+}
+
+impl PartialEq<Rope> for RopeSlice<'_> {
+    fn eq(&self, other: &Rope) -> bool {
+        *self == other.slice(..)
+    }
+}
+
+impl PartialEq<str> for Rope {
+    fn eq(&self, other: &str) -> bool {
+        self.slice(..) == *other
+    }
+}
+
+impl PartialEq<&str> for Rope {
+    fn eq(&self, other: &&str) -> bool {
+        self.slice(..) == *other
+    }
+}
+
+impl PartialEq<String> for Rope {
+    fn eq(&self, other: &String) -> bool {
+        self.slice(..) == *other
+    }
+}
+
+impl PartialEq<Rope> for str {
+    fn eq(&self, other: &Rope) -> bool {
+        other.slice(..) == *self
+    }
+}
+
+impl PartialEq<Rope> for &str {
+    fn eq(&self, other: &Rope) -> bool {
+        other.slice(..) == *self
+    }
+}
+
+impl PartialEq<Rope> for String {
+    fn eq(&self, other: &Rope) -> bool {
+        other.slice(..) == *self
+    }
+}
+
+impl TryFrom<&[u8]> for Rope {
+    type Error = RopeError;
+
+    fn try_from(value: &[u8]) -> RopeResult<Self> {
+        match std::str::from_utf8(value) {
+            Ok(value) => Ok(Rope::from(value)),
+            Err(err) => Err(RopeError::InvalidUtf8 {
+                offset: err.valid_up_to(),
+            }),
+        }
+    }
+}
+
 // TODO: Add handling lines
 #[cfg(test)]
 mod tests {
+    use std::{
+        collections::{BTreeMap, HashMap},
+        io::Read,
+        sync::Arc,
+    };
+
     use super::Rope;
+    use crate::rope::{
+        error::{RopeError, Violation},
+        internal::Internal,
+        leaf::{Leaf, MAX_LEAF_LEN, MAX_MAPPED_LEAF_LEN, MIN_LEAF_LEN},
+        metrics::Metrics,
+        node::{Node, ARC_NODE_SIZE},
+    };
 
     #[test]
     fn create_empty_rope() {
@@ -291,14 +863,14 @@ mod tests {
     fn append_thousand_words_longer_than_max_leaf_len() {
         let mut rope = Rope::new();
         let phrase = "Lorem ipsum dolor sit amet, consectetur adipiscing elit. ";
-        // Stack overflow without rebalance
         let times = 1000;
 
-        for i in 1..=times {
+        for _ in 1..=times {
             rope.append(phrase);
         }
 
         assert_eq!(times * phrase.len(), rope.len());
+        assert_eq!(times * phrase.len(), rope.value().len());
     }
 
     #[test]
@@ -470,4 +1042,1065 @@ mod tests {
 
         assert_eq!("NccWitam", rope.value());
     }
+
+    #[test]
+    fn remove_range_in_the_middle() {
+        let mut rope = Rope::from("Hello beautiful World");
+
+        rope.remove_range(5..15);
+
+        assert_eq!("Hello World", rope.value());
+        assert_eq!(11, rope.len());
+    }
+
+    #[test]
+    fn remove_range_across_concatenated_ropes() {
+        let first = Rope::from("Hello");
+        let second = Rope::from(" beautiful");
+        let third = Rope::from(" World");
+
+        let mut rope = first.concat(second).concat(third);
+        rope.remove_range(3..13);
+
+        assert_eq!("Helul World", rope.value());
+        assert_eq!(11, rope.len());
+    }
+
+    #[test]
+    fn remove_range_dropping_whole_subtree() {
+        let first = Rope::from("Hello");
+        let second = Rope::from(" beautiful");
+        let third = Rope::from(" World");
+
+        let mut rope = first.concat(second).concat(third);
+        rope.remove_range(5..15);
+
+        assert_eq!("Hello World", rope.value());
+
+        rope.insert(5, ",");
+        assert_eq!("Hello, World", rope.value());
+    }
+
+    #[test]
+    fn remove_range_utf8() {
+        let mut rope = Rope::from("Zażółć gęślą jaźń");
+
+        rope.remove_range(2..13);
+
+        assert_eq!("Zajaźń", rope.value());
+        assert_eq!(6, rope.len());
+    }
+
+    #[test]
+    fn remove_range_after_utf8_insert() {
+        let mut rope = Rope::from("ąę").concat(Rope::from("śćź"));
+
+        rope.insert(1, "żż");
+        rope.remove_range(2..5);
+
+        assert_eq!("ążćź", rope.value());
+        assert_eq!(4, rope.len());
+    }
+
+    #[test]
+    fn remove_range_longer_than_max_leaf_len() {
+        let lorem = "lorem ipsum dolor sit amet, consectetur adipiscing elit. sed do eiusmod tempor incididunt ut labore et dolore magna aliqua. ";
+        let mut expected = lorem.repeat(50);
+        let mut rope = Rope::from(expected.as_str());
+
+        rope.remove_range(100..5000);
+        expected.replace_range(100..5000, "");
+
+        assert_eq!(expected, rope.value());
+        assert_eq!(expected.len(), rope.len());
+    }
+
+    #[test]
+    fn remove_range_everything() {
+        let mut rope = Rope::from("Hello").concat(Rope::from(" World"));
+
+        rope.remove_range(0..11);
+        assert_eq!("", rope.value());
+        assert_eq!(0, rope.len());
+
+        rope.append("Again");
+        assert_eq!("Again", rope.value());
+    }
+
+    #[test]
+    fn remove_empty_range() {
+        let mut rope = Rope::from("Hello");
+
+        rope.remove_range(2..2);
+
+        assert_eq!("Hello", rope.value());
+    }
+
+    #[test]
+    #[should_panic]
+    fn remove_range_out_of_bounds() {
+        let mut rope = Rope::from("Hello");
+        rope.remove_range(2..10);
+    }
+
+    #[test]
+    fn single_line_rope() {
+        let rope = Rope::from("Hello");
+
+        assert_eq!(1, Rope::new().line_count());
+        assert_eq!(1, rope.line_count());
+        assert_eq!(0, rope.char_to_line(5));
+        assert_eq!("Hello", rope.line(0));
+    }
+
+    #[test]
+    fn line_count_and_lines() {
+        let rope = Rope::from("Hello\nświecie\n\nend");
+
+        assert_eq!(4, rope.line_count());
+        assert_eq!("Hello", rope.line(0));
+        assert_eq!("świecie", rope.line(1));
+        assert_eq!("", rope.line(2));
+        assert_eq!("end", rope.line(3));
+    }
+
+    #[test]
+    fn lines_without_crlf_endings() {
+        let rope = Rope::from("Hello\r\nświecie\n\r\nend\r");
+
+        assert_eq!("Hello\r", rope.line(0));
+        assert_eq!("Hello", rope.line_without_ending(0));
+        assert_eq!("świecie", rope.line_without_ending(1));
+        assert_eq!("", rope.line_without_ending(2));
+        // Not followed by '\n', so it's not a line ending.
+        assert_eq!("end\r", rope.line_without_ending(3));
+    }
+
+    #[test]
+    fn trailing_line_break_starts_empty_line() {
+        let rope = Rope::from("Hello\n");
+
+        assert_eq!(2, rope.line_count());
+        assert_eq!("", rope.line(1));
+        assert_eq!(6, rope.line_to_char(1));
+    }
+
+    #[test]
+    fn line_to_char_and_back() {
+        let rope = Rope::from("ab\nćd\n\nef");
+
+        assert_eq!(0, rope.line_to_char(0));
+        assert_eq!(3, rope.line_to_char(1));
+        assert_eq!(6, rope.line_to_char(2));
+        assert_eq!(7, rope.line_to_char(3));
+        assert_eq!(9, rope.line_to_char(4));
+
+        assert_eq!(0, rope.char_to_line(2));
+        assert_eq!(1, rope.char_to_line(3));
+        assert_eq!(1, rope.char_to_line(5));
+        assert_eq!(2, rope.char_to_line(6));
+        assert_eq!(3, rope.char_to_line(9));
+    }
+
+    #[test]
+    fn lines_across_leaves() {
+        let line = "lorem ipsum dolor sit amet, consectetur adipiscing elit. sed do eiusmod tempor";
+        let text = format!("{}\n", line).repeat(100);
+        let rope = Rope::from(text.as_str());
+
+        assert_eq!(101, rope.line_count());
+        for i in 0..100 {
+            assert_eq!(line, rope.line(i));
+            assert_eq!(i * (line.len() + 1), rope.line_to_char(i));
+            assert_eq!(i, rope.char_to_line(i * (line.len() + 1) + 3));
+        }
+    }
+
+    #[test]
+    fn lines_after_insert_and_concat() {
+        let mut rope = Rope::from("first").concat(Rope::from("second\nthird"));
+
+        rope.insert(5, "\n");
+        rope.insert(0, "zero\n");
+
+        assert_eq!(4, rope.line_count());
+        assert_eq!("zero", rope.line(0));
+        assert_eq!("first", rope.line(1));
+        assert_eq!("second", rope.line(2));
+        assert_eq!("third", rope.line(3));
+    }
+
+    #[test]
+    fn lines_after_removal() {
+        let mut rope = Rope::from("one\ntwo").concat(Rope::from("\nthree\nfour"));
+
+        rope.remove_at(3);
+        assert_eq!(3, rope.line_count());
+        assert_eq!("onetwo", rope.line(0));
+
+        rope.remove_range(6..13);
+        assert_eq!(1, rope.line_count());
+        assert_eq!("onetwofour", rope.line(0));
+    }
+
+    #[test]
+    fn split_shares_untouched_subtrees() {
+        // Big enough for leaves not to be merged with each other.
+        let first = Rope::from("Hello ".repeat(50).as_str());
+        let second = Rope::from("beautiful ".repeat(30).as_str());
+        let third = Rope::from("World ".repeat(50).as_str());
+        let rope = first.concat(second.clone()).concat(third.clone());
+
+        let (left, right) = rope.split_at(3);
+
+        assert_eq!("Hel", left.value());
+        assert_eq!(rope.value()[3..], right.value());
+        let Node::Internal(right_root) = right.root.as_ref() else {
+            panic!("Expected internal node");
+        };
+        assert!(Arc::ptr_eq(&right_root.branches[1], &third.root));
+        let Node::Internal(right_left) = right_root.branches[0].as_ref() else {
+            panic!("Expected internal node");
+        };
+        assert!(Arc::ptr_eq(&right_left.branches[1], &second.root));
+    }
+
+    #[test]
+    fn split_at_branch_boundary() {
+        let rope = Rope::from("Hello").concat(Rope::from(" World"));
+
+        let (left, right) = rope.split_at(5);
+
+        assert_eq!("Hello", left.value());
+        assert_eq!(" World", right.value());
+    }
+
+    #[test]
+    fn split_longer_than_max_leaf_len() {
+        let lorem = "lorem ipsum dolor sit amet,\nconsectetur adipiscing elit. ";
+        let text = lorem.repeat(100);
+        let rope = Rope::from(text.as_str());
+
+        for index in [1, 999, 1000, 3001, text.len() - 1] {
+            let (left, right) = rope.split_at(index);
+
+            assert_eq!(&text[..index], left.value());
+            assert_eq!(&text[index..], right.value());
+            assert_eq!(text[..index].matches('\n').count() + 1, left.line_count());
+            assert_eq!(text[index..].matches('\n').count() + 1, right.line_count());
+        }
+    }
+
+    #[test]
+    fn split_and_edit_both_halves() {
+        let rope = Rope::from("Hello").concat(Rope::from(" World"));
+
+        let (mut left, mut right) = rope.split_at(3);
+        left.append("p");
+        right.insert(0, "!");
+
+        assert_eq!("Help", left.value());
+        assert_eq!("!lo World", right.value());
+        assert_eq!("Hello World", rope.value());
+    }
+
+    #[test]
+    fn appending_keeps_rope_balanced() {
+        let lorem = "lorem ipsum dolor sit amet, consectetur adipiscing elit. sed do eiusmod tempor incididunt ut labore et dolore magna aliqua. ";
+        let mut rope = Rope::new();
+
+        for _ in 0..6000 {
+            rope.append(lorem);
+        }
+
+        // 6000 * 124 bytes take around 750 full leaves.
+        assert!(rope.root.height() <= 15, "height {}", rope.root.height());
+        assert_eq!(lorem.repeat(6000), rope.value());
+    }
+
+    #[test]
+    fn inserting_at_start_keeps_rope_balanced() {
+        let mut rope = Rope::new();
+        let mut expected = String::new();
+
+        for i in 0..5000 {
+            let word = format!("{} ", i);
+            rope.insert(0, &word);
+            expected.insert_str(0, &word);
+        }
+
+        assert!(rope.root.height() <= 15, "height {}", rope.root.height());
+        assert_eq!(expected, rope.value());
+    }
+
+    #[test]
+    fn concatenating_many_ropes_keeps_rope_balanced() {
+        let mut rope = Rope::new();
+
+        for i in 0..1000 {
+            rope = rope.concat(Rope::from(format!("{}\n", i).as_str()));
+        }
+
+        assert!(rope.root.height() <= 15, "height {}", rope.root.height());
+        assert_eq!(1001, rope.line_count());
+        assert_eq!("999", rope.line(999));
+    }
+
+    #[test]
+    fn removing_keeps_rope_balanced() {
+        let mut rope = Rope::new();
+        for i in 0..1000 {
+            rope = rope.concat(Rope::from(format!("{}\n", i).as_str()));
+        }
+
+        rope.remove_range(0..1500);
+        rope.remove_range(100..2000);
+
+        assert!(rope.root.height() <= 13, "height {}", rope.root.height());
+        assert_eq!(rope.len(), rope.value().chars().count());
+    }
+
+    #[test]
+    fn rebalance_keeps_content() {
+        let mut rope = Rope::new();
+        for i in 0..1000 {
+            rope.insert(0, format!("{}\n", i).as_str());
+        }
+        let before = rope.value();
+
+        rope.rebalance();
+
+        assert_eq!(before, rope.value());
+        assert_eq!(1001, rope.line_count());
+        assert_eq!("999", rope.line(0));
+    }
+
+    #[test]
+    fn from_utf8_string_longer_than_max_leaf_len() {
+        let text = "zażółć gęślą jaźń\n".repeat(200);
+
+        let rope = Rope::from(text.as_str());
+
+        assert_eq!(text, rope.value());
+        assert_eq!(text.chars().count(), rope.len());
+        assert_eq!(201, rope.line_count());
+    }
+
+    #[test]
+    fn chunks_of_empty_rope() {
+        let rope = Rope::new();
+
+        assert_eq!(0, rope.chunks().count());
+        assert_eq!(None, rope.chars().next());
+        assert_eq!(None, rope.chars().next_back());
+    }
+
+    #[test]
+    fn chunks_cover_whole_rope() {
+        let text = "zażółć gęślą jaźń\n".repeat(200);
+        let rope = Rope::from(text.as_str());
+
+        let chunks: Vec<&str> = rope.chunks().collect();
+        assert!(chunks.len() > 1);
+        assert_eq!(text, chunks.concat());
+
+        let mut reversed: Vec<&str> = rope.chunks().rev().collect();
+        reversed.reverse();
+        assert_eq!(chunks, reversed);
+    }
+
+    #[test]
+    fn chars_and_bytes_both_ways() {
+        let text = "Hello, świecie! ".repeat(100);
+        let rope = Rope::from(text.as_str());
+
+        assert!(rope.chars().eq(text.chars()));
+        assert!(rope.chars().rev().eq(text.chars().rev()));
+        assert!(rope.bytes().eq(text.bytes()));
+        assert!(rope.bytes().rev().eq(text.bytes().rev()));
+    }
+
+    #[test]
+    fn chars_from_both_ends_meet_in_the_middle() {
+        let text = "lorem ipsum dolor sit amet ".repeat(100);
+        let rope = Rope::from(text.as_str());
+        let mut chars = rope.chars();
+        let mut front = String::new();
+        let mut back = String::new();
+
+        loop {
+            match (chars.next(), chars.next_back()) {
+                (Some(a), Some(b)) => {
+                    front.push(a);
+                    back.insert(0, b);
+                }
+                (Some(a), None) => front.push(a),
+                _ => break,
+            }
+        }
+
+        assert_eq!(text, front + &back);
+    }
+
+    #[test]
+    fn chars_at_seeks_to_index() {
+        let text = "ąbć\n".repeat(500);
+        let rope = Rope::from(text.as_str());
+
+        for index in [0, 1, 999, 1000, 1999, 2000] {
+            let expected: String = text.chars().skip(index).collect();
+            assert_eq!(expected, rope.chars_at(index).collect::<String>());
+        }
+    }
+
+    #[test]
+    fn chars_after_edits() {
+        let mut rope = Rope::from("Hello").concat(Rope::from(" World"));
+
+        rope.insert(5, ",");
+        rope.remove_range(0..1);
+
+        assert_eq!("ello, World", rope.chars().collect::<String>());
+        assert_eq!("dlroW ,olle", rope.chars().rev().collect::<String>());
+    }
+
+    #[test]
+    fn try_insert_out_of_bounds() {
+        let mut rope = Rope::from("Hello");
+
+        let result = rope.try_insert(10, "whatever");
+
+        assert_eq!(
+            Err(RopeError::IndexOutOfBounds { index: 10, len: 5 }),
+            result
+        );
+        assert_eq!("Hello", rope.value());
+    }
+
+    #[test]
+    fn try_remove_out_of_bounds() {
+        let mut rope = Rope::from("Hello");
+        let (start, end) = (3, 2);
+
+        assert_eq!(
+            Err(RopeError::IndexOutOfBounds { index: 5, len: 5 }),
+            rope.try_remove_at(5)
+        );
+        assert_eq!(
+            Err(RopeError::IndexOutOfBounds { index: 7, len: 5 }),
+            rope.try_remove_range(2..7)
+        );
+        assert_eq!(
+            Err(RopeError::InvalidRange { start: 3, end: 2 }),
+            rope.try_remove_range(start..end)
+        );
+        assert_eq!("Hello", rope.value());
+    }
+
+    #[test]
+    fn try_remove_from_empty_rope() {
+        let mut rope = Rope::new();
+
+        assert_eq!(
+            Err(RopeError::IndexOutOfBounds { index: 5, len: 0 }),
+            rope.try_remove_at(5)
+        );
+        assert_eq!(
+            Err(RopeError::IndexOutOfBounds { index: 0, len: 0 }),
+            rope.try_remove_at(0)
+        );
+        assert_eq!(0, rope.len());
+    }
+
+    #[test]
+    fn try_split_and_slice_out_of_bounds() {
+        let rope = Rope::from("Hello");
+
+        assert!(rope.try_split_at(6).is_err());
+        assert!(rope.try_slice(..6).is_err());
+        assert!(rope.try_line(1).is_err());
+        assert!(rope.try_line_to_char(2).is_err());
+        assert!(rope.try_char_to_line(6).is_err());
+        assert!(rope.try_chars_at(6).is_err());
+    }
+
+    #[test]
+    fn try_operations_within_bounds() {
+        let mut rope = Rope::from("Hello");
+
+        rope.try_insert(5, " World").unwrap();
+        rope.try_remove_at(0).unwrap();
+        let (left, right) = rope.try_split_at(4).unwrap();
+
+        assert_eq!("ello", left.value());
+        assert_eq!(" World", right.value());
+        assert_eq!("llo", rope.try_slice(1..4).unwrap());
+    }
+
+    #[test]
+    fn rope_from_invalid_utf8() {
+        let bytes = [b'a', b'b', 0xC5, b'c'];
+
+        let result = Rope::try_from(&bytes[..]);
+
+        assert_eq!(RopeError::InvalidUtf8 { offset: 2 }, result.unwrap_err());
+        assert_eq!("aś", Rope::try_from("aś".as_bytes()).unwrap().value());
+    }
+
+    // Hands out at most `step` bytes per read, cutting multi-byte chars in half.
+    struct SlowReader<'a> {
+        data: &'a [u8],
+        step: usize,
+    }
+
+    impl Read for SlowReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = self.step.min(buf.len()).min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn from_empty_reader() {
+        let rope = Rope::from_reader("".as_bytes()).unwrap();
+
+        assert_eq!(0, rope.len());
+        assert_eq!("", rope.value());
+    }
+
+    #[test]
+    fn from_reader_longer_than_max_leaf_len() {
+        let text = "zażółć gęślą jaźń\n".repeat(1000);
+
+        let rope = Rope::from_reader(text.as_bytes()).unwrap();
+
+        assert_eq!(text, rope.value());
+        assert_eq!(text.chars().count(), rope.len());
+        assert_eq!(1001, rope.line_count());
+        assert!(rope.root.height() <= 6, "height {}", rope.root.height());
+    }
+
+    #[test]
+    fn from_reader_with_chars_cut_between_reads() {
+        let text = "ąę🦀ś".repeat(300);
+
+        for step in [1, 3, 5, 1000] {
+            let reader = SlowReader {
+                data: text.as_bytes(),
+                step,
+            };
+            let rope = Rope::from_reader(reader).unwrap();
+
+            assert_eq!(text, rope.value());
+            assert_eq!(text.chars().count(), rope.len());
+        }
+    }
+
+    #[test]
+    fn from_reader_then_edit() {
+        let text = "lorem ipsum\n".repeat(500);
+        let mut rope = Rope::from_reader(text.as_bytes()).unwrap();
+
+        rope.insert(6, "dolor ");
+        rope.remove_range(0..6);
+
+        assert_eq!("dolor ipsum", rope.line(0));
+        assert_eq!(501, rope.line_count());
+    }
+
+    #[test]
+    fn from_reader_reports_invalid_utf8_offset() {
+        let mut bytes = "a".repeat(2000).into_bytes();
+        bytes.push(0xFF);
+        bytes.extend_from_slice(b"tail");
+
+        let err = Rope::from_reader(&bytes[..]).unwrap_err();
+
+        assert_eq!(std::io::ErrorKind::InvalidData, err.kind());
+        let inner = err.into_inner().unwrap().downcast::<RopeError>().unwrap();
+        assert_eq!(RopeError::InvalidUtf8 { offset: 2000 }, *inner);
+    }
+
+    #[test]
+    fn from_reader_reports_truncated_char_at_end() {
+        let mut bytes = "abc".as_bytes().to_vec();
+        bytes.push("ś".as_bytes()[0]);
+
+        let err = Rope::from_reader(&bytes[..]).unwrap_err();
+
+        let inner = err.into_inner().unwrap().downcast::<RopeError>().unwrap();
+        assert_eq!(RopeError::InvalidUtf8 { offset: 3 }, *inner);
+    }
+
+    #[test]
+    fn write_to_writer() {
+        let text = "zażółć gęślą jaźń\n".repeat(300);
+        let mut rope = Rope::from(text.as_str());
+        rope.insert(5, "ś");
+        let mut expected = text.clone();
+        expected.insert(8, 'ś');
+
+        let mut out = vec![];
+        rope.write_to(&mut out).unwrap();
+
+        assert_eq!(expected.as_bytes(), &out[..]);
+    }
+
+    #[test]
+    fn write_empty_rope() {
+        let mut out = vec![];
+
+        Rope::new().write_to(&mut out).unwrap();
+
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn display_rope() {
+        let rope = Rope::from("Hello").concat(Rope::from(", świecie"));
+
+        assert_eq!("Hello, świecie", rope.to_string());
+        assert_eq!("[Hello, świecie]", format!("[{}]", rope));
+    }
+
+    #[test]
+    fn heap_size_is_proportional_to_content() {
+        let mut rope = Rope::new();
+        for _ in 0..1000 {
+            rope = rope.concat(Rope::from("a\n"));
+        }
+
+        // 1000 two-byte leaves, the internal nodes joining them and nothing more.
+        assert!(
+            rope.heap_size() < 1000 * 3 * ARC_NODE_SIZE,
+            "{}",
+            rope.heap_size()
+        );
+    }
+
+    #[test]
+    fn heap_size_of_full_leaves() {
+        let text = "lorem ipsum dolor sit amet\n".repeat(1000);
+
+        let rope = Rope::from_reader(text.as_bytes()).unwrap();
+
+        assert!(
+            rope.heap_size() < text.len() + text.len() / 5,
+            "{}",
+            rope.heap_size()
+        );
+    }
+
+    #[test]
+    fn concatenating_short_lines_merges_leaves() {
+        let mut rope = Rope::new();
+        for _ in 0..1000 {
+            rope = rope.concat(Rope::from("a\n"));
+        }
+
+        assert_eq!("a\n".repeat(1000), rope.value());
+        assert!(
+            rope.chunks().count() <= 2000 / MIN_LEAF_LEN,
+            "{}",
+            rope.chunks().count()
+        );
+    }
+
+    #[test]
+    fn backspacing_through_paragraph_does_not_fragment_rope() {
+        let text = "lorem ipsum dolor sit amet\n".repeat(200);
+        let mut rope = Rope::from_reader(text.as_bytes()).unwrap();
+        let leaves = rope.chunks().count();
+
+        for index in (1000..4000).rev() {
+            rope.remove_at(index);
+        }
+
+        let expected = format!("{}{}", &text[..1000], &text[4000..]);
+        assert_eq!(expected, rope.value());
+        assert!(rope.chunks().count() <= leaves, "{}", rope.chunks().count());
+    }
+
+    #[test]
+    fn typing_in_the_middle_does_not_fragment_rope() {
+        let mut rope = Rope::from("Hello World");
+
+        for (idx, c) in "beautiful ".chars().enumerate() {
+            rope.insert(6 + idx, &c.to_string());
+        }
+
+        assert_eq!("Hello beautiful World", rope.value());
+        assert_eq!(1, rope.chunks().count());
+    }
+
+    #[test]
+    fn removing_leaves_small_remainders_merged() {
+        let text = "lorem ipsum dolor sit amet\n".repeat(200);
+        let mut rope = Rope::from_reader(text.as_bytes()).unwrap();
+
+        rope.remove_range(10..text.len() - 10);
+
+        assert_eq!(
+            format!("{}{}", &text[..10], &text[text.len() - 10..]),
+            rope.value()
+        );
+        assert_eq!(1, rope.chunks().count());
+        assert_eq!(2, rope.line_count());
+    }
+
+    #[test]
+    fn edited_rope_keeps_invariants() {
+        let text = "lorem ipsum dolor sit amet\n".repeat(200);
+        let mut rope = Rope::from(text.as_str());
+
+        rope.insert(1000, "zażółć gęślą jaźń");
+        rope.remove_range(10..2000);
+        let rope = rope.concat(Rope::from("end"));
+
+        assert_eq!(Ok(()), rope.check_invariants());
+    }
+
+    #[test]
+    fn check_invariants_reports_wrong_weight() {
+        let root = Internal::with_shared_branches(
+            Arc::new(Node::from("Hello")),
+            Arc::new(Node::from(" World")),
+            Metrics {
+                chars: 3,
+                ..Metrics::of(b"Hello")
+            },
+        );
+        let rope = Rope {
+            root: Arc::new(Node::from(root)),
+            len: 11,
+        };
+
+        let err = rope.check_invariants().unwrap_err();
+
+        assert_eq!("", err.path);
+        assert_eq!(
+            Violation::WrongWeight {
+                expected: 5,
+                found: 3
+            },
+            err.violation
+        );
+        assert_eq!("Weight is 3 instead of 5 at root", err.to_string());
+    }
+
+    #[test]
+    fn invalid_utf8_never_reaches_a_leaf() {
+        let err = Leaf::try_from(&[b'a', 0xff][..]).unwrap_err();
+        assert_eq!(RopeError::InvalidUtf8 { offset: 1 }, err);
+
+        let root = Internal::with_shared_branches(
+            Arc::new(Node::from("Hello")),
+            Arc::new(Node::from(Leaf::from_bytes("aż".as_bytes()))),
+            Metrics::of(b"Hello"),
+        );
+        let rope = Rope {
+            root: Arc::new(Node::from(root)),
+            len: 7,
+        };
+
+        assert!(rope.check_invariants().is_ok());
+        assert_eq!("Helloaż", rope.chunks().collect::<String>());
+    }
+
+    #[test]
+    fn check_invariants_reports_stale_length() {
+        let rope = Rope {
+            root: Rope::from("Hello").root,
+            len: 4,
+        };
+
+        assert_eq!(
+            Violation::StaleLength {
+                expected: 5,
+                found: 4
+            },
+            rope.check_invariants().unwrap_err().violation
+        );
+    }
+
+    #[test]
+    fn debug_tree_shows_leaves_and_sharing() {
+        let rope = Rope::from("Hello\nWorld");
+        let _copy = rope.clone();
+
+        assert_eq!(
+            format!(
+                "Leaf 11/{} bytes ({}%) chars=11 lines=1 \"Hello\\nWorld\" depth=0 refs=2\n",
+                MAX_LEAF_LEN,
+                1100 / MAX_LEAF_LEN
+            ),
+            rope.debug_tree()
+        );
+    }
+
+    #[test]
+    fn debug_tree_of_internal_nodes() {
+        let text = "a".repeat(600);
+        let rope = Rope::from(text.as_str()).concat(Rope::from(text.as_str()));
+
+        let lines: Vec<String> = rope.debug_tree().lines().map(String::from).collect();
+
+        assert_eq!(3, lines.len());
+        assert!(lines[0].starts_with("Internal height=1 weight=600 line_weight=0 depth=0"));
+        let leaf = format!(
+            "  Leaf 600/{} bytes ({}%) chars=600",
+            MAX_LEAF_LEN,
+            60000 / MAX_LEAF_LEN
+        );
+        assert!(lines[1].starts_with(&leaf));
+        assert!(lines[2].ends_with("\"aaaaaaaaaaaaaaaa\"… depth=1 refs=1"));
+    }
+
+    #[test]
+    fn graphviz_draws_shared_subtrees_once() {
+        let half = Rope::from("a".repeat(600).as_str());
+        let rope = half.clone().concat(half.clone());
+
+        let dot = rope.debug_tree_graphviz();
+
+        assert!(dot.starts_with("digraph rope {\n"));
+        assert!(dot.ends_with("}\n"));
+        assert_eq!(
+            1,
+            dot.matches(&format!("Leaf 600/{}", MAX_LEAF_LEN)).count()
+        );
+        assert_eq!(2, dot.matches(" -> ").count());
+        assert!(dot.contains("refs=3"));
+    }
+
+    #[test]
+    fn convert_between_chars_bytes_and_utf16() {
+        let rope = Rope::from("aż😀b\n");
+
+        assert_eq!(5, rope.len());
+        assert_eq!(9, rope.len_bytes());
+        assert_eq!(6, rope.len_utf16());
+        assert_eq!(
+            vec![0, 1, 3, 7, 8, 9],
+            (0..=5).map(|i| rope.char_to_byte(i)).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![0, 1, 2, 4, 5, 6],
+            (0..=5).map(|i| rope.char_to_utf16(i)).collect::<Vec<_>>()
+        );
+        assert_eq!(3, rope.byte_to_char(7));
+        assert_eq!(3, rope.utf16_to_char(4));
+        assert_eq!(5, rope.utf16_to_char(6));
+    }
+
+    #[test]
+    fn conversions_across_many_leaves() {
+        let text = "zażółć 😀 gęślą jaźń\n".repeat(300);
+        let mut rope = Rope::from(text.as_str());
+        rope.insert(1000, "🇵🇱");
+        rope.remove_range(3000..3100);
+        let text = rope.value();
+
+        let mut utf16 = 0;
+        for (index, (byte, c)) in text.char_indices().enumerate() {
+            assert_eq!(byte, rope.char_to_byte(index));
+            assert_eq!(index, rope.byte_to_char(byte));
+            assert_eq!(utf16, rope.char_to_utf16(index));
+            assert_eq!(index, rope.utf16_to_char(utf16));
+            utf16 += c.len_utf16();
+        }
+        assert_eq!(text.len(), rope.len_bytes());
+        assert_eq!(utf16, rope.len_utf16());
+        assert_eq!(text.len(), rope.char_to_byte(rope.len()));
+        assert_eq!(Ok(()), rope.check_invariants());
+    }
+
+    #[test]
+    fn conversions_reject_offsets_inside_chars() {
+        let rope = Rope::from("aż😀");
+
+        assert_eq!(Err(RopeError::NotCharBoundary(2)), rope.try_byte_to_char(2));
+        assert_eq!(
+            Err(RopeError::NotUtf16Boundary(3)),
+            rope.try_utf16_to_char(3)
+        );
+        assert_eq!(
+            Err(RopeError::IndexOutOfBounds { index: 8, len: 7 }),
+            rope.try_byte_to_char(8)
+        );
+        assert_eq!(
+            Err(RopeError::IndexOutOfBounds { index: 4, len: 3 }),
+            rope.try_char_to_utf16(4)
+        );
+    }
+
+    #[test]
+    fn move_by_graphemes() {
+        // e + combining acute accent, a flag, a family and CRLF each form a single cluster.
+        let rope = Rope::from("ae\u{301}🇵🇱👨\u{200D}👩\u{200D}👧\r\nb");
+        let mut boundaries = vec![0];
+        while *boundaries.last().unwrap() < rope.len() {
+            boundaries.push(rope.next_grapheme(*boundaries.last().unwrap()));
+        }
+
+        assert_eq!(vec![0, 1, 3, 5, 10, 12, 13], boundaries);
+        assert_eq!(10, rope.prev_grapheme(12));
+        assert_eq!(5, rope.prev_grapheme(10));
+        assert_eq!(5, rope.prev_grapheme(7));
+        assert_eq!(10, rope.next_grapheme(7));
+        assert_eq!(0, rope.prev_grapheme(0));
+        assert_eq!(13, rope.next_grapheme(13));
+    }
+
+    #[test]
+    fn graphemes_spanning_leaves() {
+        // Too long to be merged into a single leaf.
+        let left = Rope::from(format!("{}👨", "a".repeat(980)).as_str());
+        let right = Rope::from("\u{200D}👩\u{200D}👧 end");
+        let rope = left.concat(right);
+        assert_eq!(2, rope.chunks().count());
+
+        assert_eq!(985, rope.next_grapheme(980));
+        assert_eq!(980, rope.prev_grapheme(985));
+        assert_eq!(980, rope.prev_grapheme(982));
+        assert_eq!(986, rope.next_grapheme(985));
+    }
+
+    #[test]
+    fn compare_ropes_by_content() {
+        let rope = Rope::from("Hello World");
+        let built = Rope::from("Hello").concat(Rope::from(" World"));
+        let longer = Rope::from("Hello World!");
+        let lowercase = Rope::from("Hello world");
+        let prefix = Rope::from("Hello");
+        let (z, z_dot) = (Rope::from("z"), Rope::from("ż"));
+        let world = Rope::from("World");
+
+        assert_eq!(rope, built);
+        assert_eq!(rope, "Hello World");
+        assert_eq!(String::from("Hello World"), rope);
+        assert_ne!(rope, longer);
+        assert!(rope < lowercase);
+        assert!(prefix < rope);
+        assert!(z_dot > z);
+        assert_eq!(rope.slice(6..), world);
+    }
+
+    #[test]
+    fn compare_edited_clone() {
+        let text = "some longer text\n".repeat(500);
+        let rope = Rope::from_reader(text.as_bytes()).unwrap();
+        let mut clone = rope.clone();
+        assert_eq!(rope, clone);
+
+        clone.insert(8000, "x");
+        assert!(rope < clone);
+        clone.remove_at(8000);
+        assert_eq!(rope, clone);
+    }
+
+    #[test]
+    fn ropes_as_map_keys() {
+        let mut hashed = HashMap::new();
+        let mut ordered = BTreeMap::new();
+        for (value, text) in ["b", "a", "ab"].iter().enumerate() {
+            hashed.insert(Rope::from(*text), value);
+            ordered.insert(Rope::from(*text), value);
+        }
+
+        assert_eq!(
+            Some(&2),
+            hashed.get(&Rope::from("a").concat(Rope::from("b")))
+        );
+        let keys: Vec<String> = ordered.keys().map(Rope::value).collect();
+        assert_eq!(vec!["a", "ab", "b"], keys);
+    }
+
+    #[test]
+    fn rope_from_file_reads_leaves_from_map() {
+        let path = std::env::temp_dir().join("editorus_rope_from_file_reads_leaves_from_map.txt");
+        let text = "zażółć gęślą jaźń 😀\n".repeat(10000);
+        std::fs::write(&path, &text).unwrap();
+
+        let mut rope = Rope::from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(rope, text);
+        assert!(rope.check_invariants().is_ok());
+        // Only the tree itself is allocated, with a node per MAX_MAPPED_LEAF_LEN bytes.
+        assert!(rope.heap_size() < text.len() / 100);
+
+        // Edited leaves are cut into regular ones, only the edited one of those is copied.
+        let before = rope.heap_size();
+        rope.insert(5000, "inserted");
+        rope.remove_range(150000..150010);
+        assert!(rope.heap_size() - before < MAX_MAPPED_LEAF_LEN / 2);
+
+        let mut expected: Vec<char> = text.chars().collect();
+        expected.splice(5000..5000, "inserted".chars());
+        expected.drain(150000..150010);
+        assert_eq!(rope, expected.into_iter().collect::<String>());
+        assert!(rope.check_invariants().is_ok());
+    }
+
+    #[test]
+    fn debug_tree_of_mapped_leaves() {
+        let path = std::env::temp_dir().join("editorus_debug_tree_of_mapped_leaves.txt");
+        std::fs::write(&path, "a".repeat(MAX_MAPPED_LEAF_LEN + 100)).unwrap();
+        let rope = Rope::from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let lines: Vec<String> = rope.debug_tree().lines().map(String::from).collect();
+
+        assert_eq!(3, lines.len());
+        let full = format!("  Leaf {0}/{0} bytes (100%)", MAX_MAPPED_LEAF_LEN);
+        assert!(lines[1].starts_with(&full));
+        let rest = format!("  Leaf 100/{} bytes (0%)", MAX_MAPPED_LEAF_LEN);
+        assert!(lines[2].starts_with(&rest));
+    }
+
+    #[test]
+    fn file_changed_after_opening() {
+        use std::io::{Seek, SeekFrom, Write};
+
+        let path = std::env::temp_dir().join("editorus_file_changed_after_opening.txt");
+        let text = "zażółć gęślą jaźń\n".repeat(100);
+        std::fs::write(&path, &text).unwrap();
+        let rope = Rope::from_file(&path).unwrap();
+
+        // Rewritten in place, then truncated, like a log rotated with copytruncate.
+        let mut file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.seek(SeekFrom::Start(2)).unwrap();
+        file.write_all(&[0xFF]).unwrap();
+        file.sync_all().unwrap();
+        assert_eq!(rope, text);
+        file.set_len(10).unwrap();
+        file.sync_all().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // The rope reads from its own snapshot, so it still holds the content it was opened with.
+        assert_eq!(rope, text);
+        assert_eq!(text.chars().count(), rope.chars().count());
+        assert!(rope.check_invariants().is_ok());
+    }
+
+    #[test]
+    fn rope_from_empty_or_invalid_file() {
+        let path = std::env::temp_dir().join("editorus_rope_from_empty_or_invalid_file.txt");
+        std::fs::write(&path, "").unwrap();
+        let empty = Rope::from_file(&path).unwrap();
+
+        let mut bytes = "ą".repeat(MAX_LEAF_LEN).into_bytes();
+        bytes[1500] = 0xFF;
+        std::fs::write(&path, &bytes).unwrap();
+        let err = Rope::from_file(&path).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(0, empty.len());
+        assert_eq!(std::io::ErrorKind::InvalidData, err.kind());
+        let inner = err.into_inner().unwrap().downcast::<RopeError>().unwrap();
+        assert_eq!(RopeError::InvalidUtf8 { offset: 1500 }, *inner);
+    }
 }
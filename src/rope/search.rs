@@ -0,0 +1,463 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    iter::Rev,
+    ops::Range,
+};
+
+use super::iter::Chars;
+
+// Search runs over the stream of chars, one chunk after another, so matches spanning leaf
+// boundaries are found without ever building a `String` of the whole rope. All positions are
+// char indices. Empty patterns never match.
+
+// Case insensitive matching compares chars lowercased one to one. Chars whose lowercase form is
+// longer than a single char are compared as they are.
+fn fold(c: char, ignore_case: bool) -> char {
+    if !ignore_case {
+        return c;
+    }
+    let mut lower = c.to_lowercase();
+    match (lower.next(), lower.next()) {
+        (Some(l), None) => l,
+        _ => c,
+    }
+}
+
+// Single pattern, prepared for Knuth-Morris-Pratt matching so no char of the text is read twice.
+#[derive(Clone, Debug)]
+pub struct Pattern {
+    source: Vec<char>,
+    chars: Vec<char>,
+    // Length of the longest proper prefix of chars[..=i] which is also its suffix.
+    failure: Vec<usize>,
+    ignore_case: bool,
+}
+
+impl Pattern {
+    pub fn new(pattern: &str) -> Self {
+        Self::build(pattern.chars().collect(), false)
+    }
+
+    pub fn ignore_case(self) -> Self {
+        Self::build(self.source, true)
+    }
+
+    // Number of chars matched by this pattern.
+    pub fn len(&self) -> usize {
+        self.chars.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chars.is_empty()
+    }
+
+    fn build(source: Vec<char>, ignore_case: bool) -> Self {
+        let chars: Vec<char> = source.iter().map(|c| fold(*c, ignore_case)).collect();
+        let mut failure = vec![0; chars.len()];
+        let mut k = 0;
+        for i in 1..chars.len() {
+            while k > 0 && chars[i] != chars[k] {
+                k = failure[k - 1];
+            }
+            if chars[i] == chars[k] {
+                k += 1;
+            }
+            failure[i] = k;
+        }
+
+        Self {
+            source,
+            chars,
+            failure,
+            ignore_case,
+        }
+    }
+
+    fn reversed(&self) -> Self {
+        Self::build(
+            self.source.iter().rev().copied().collect(),
+            self.ignore_case,
+        )
+    }
+
+    // Advances matched prefix length by one char of the text.
+    fn step(&self, mut state: usize, c: char) -> usize {
+        let c = fold(c, self.ignore_case);
+        while state > 0 && self.chars[state] != c {
+            state = self.failure[state - 1];
+        }
+        if self.chars[state] == c {
+            state += 1;
+        }
+        state
+    }
+}
+
+impl From<&str> for Pattern {
+    fn from(pattern: &str) -> Self {
+        Pattern::new(pattern)
+    }
+}
+
+impl From<&String> for Pattern {
+    fn from(pattern: &String) -> Self {
+        Pattern::new(pattern)
+    }
+}
+
+// First match of the pattern in given chars. Returns number of chars read up to its end.
+fn find_end<I: Iterator<Item = char>>(pattern: &Pattern, chars: I) -> Option<usize> {
+    if pattern.is_empty() {
+        return None;
+    }
+    let mut state = 0;
+    for (idx, c) in chars.enumerate() {
+        state = pattern.step(state, c);
+        if state == pattern.len() {
+            return Some(idx + 1);
+        }
+    }
+    None
+}
+
+pub(crate) fn find(pattern: &Pattern, chars: Chars<'_>) -> Option<usize> {
+    find_end(pattern, chars).map(|end| end - pattern.len())
+}
+
+// Searches backwards for the reversed pattern, `len` being the number of chars searched.
+pub(crate) fn rfind(pattern: &Pattern, chars: Rev<Chars<'_>>, len: usize) -> Option<usize> {
+    find_end(&pattern.reversed(), chars).map(|end| len - end)
+}
+
+// Non-overlapping matches of a single pattern, from left to right.
+#[derive(Clone, Debug)]
+pub struct FindIter<'a> {
+    chars: Chars<'a>,
+    pattern: Pattern,
+    state: usize,
+    position: usize,
+}
+
+impl<'a> FindIter<'a> {
+    pub(crate) fn new(chars: Chars<'a>, pattern: Pattern) -> Self {
+        Self {
+            chars,
+            pattern,
+            state: 0,
+            position: 0,
+        }
+    }
+}
+
+impl Iterator for FindIter<'_> {
+    type Item = Range<usize>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pattern.is_empty() {
+            return None;
+        }
+        for c in self.chars.by_ref() {
+            self.position += 1;
+            self.state = self.pattern.step(self.state, c);
+            if self.state == self.pattern.len() {
+                self.state = 0;
+                return Some(self.position - self.pattern.len()..self.position);
+            }
+        }
+        None
+    }
+}
+
+// Set of patterns matched all at once with an Aho-Corasick automaton, e.g. for highlighting
+// every occurrence of several words in a single pass.
+#[derive(Clone, Debug)]
+pub struct MultiPattern {
+    sources: Vec<Vec<char>>,
+    states: Vec<State>,
+    ignore_case: bool,
+}
+
+#[derive(Clone, Debug, Default)]
+struct State {
+    next: HashMap<char, usize>,
+    // Longest proper suffix of this state which is also a state.
+    fail: usize,
+    // Patterns ending here, including those ending in states reachable by `fail`.
+    matches: Vec<usize>,
+}
+
+// Occurrence of one of the patterns of `MultiPattern`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Match {
+    // Index of the matched pattern.
+    pub pattern: usize,
+    pub range: Range<usize>,
+}
+
+impl MultiPattern {
+    pub fn new<S: AsRef<str>>(patterns: &[S]) -> Self {
+        let sources = patterns
+            .iter()
+            .map(|p| p.as_ref().chars().collect())
+            .collect();
+        Self::build(sources, false)
+    }
+
+    pub fn ignore_case(self) -> Self {
+        Self::build(self.sources, true)
+    }
+
+    fn build(sources: Vec<Vec<char>>, ignore_case: bool) -> Self {
+        let mut states = vec![State::default()];
+
+        for (id, pattern) in sources.iter().enumerate() {
+            if pattern.is_empty() {
+                continue;
+            }
+            let mut current = 0;
+            for c in pattern {
+                let c = fold(*c, ignore_case);
+                current = match states[current].next.get(&c) {
+                    Some(next) => *next,
+                    None => {
+                        states.push(State::default());
+                        let next = states.len() - 1;
+                        states[current].next.insert(c, next);
+                        next
+                    }
+                };
+            }
+            states[current].matches.push(id);
+        }
+
+        // Breadth first, so fail links of shallower states are ready when needed.
+        let mut queue: VecDeque<usize> = states[0].next.values().copied().collect();
+        while let Some(state) = queue.pop_front() {
+            let transitions: Vec<(char, usize)> =
+                states[state].next.iter().map(|(c, s)| (*c, *s)).collect();
+            for (c, next) in transitions {
+                let mut fail = states[state].fail;
+                let target = loop {
+                    if let Some(target) = states[fail].next.get(&c) {
+                        break *target;
+                    }
+                    if fail == 0 {
+                        break 0;
+                    }
+                    fail = states[fail].fail;
+                };
+                states[next].fail = target;
+                let inherited = states[target].matches.clone();
+                states[next].matches.extend(inherited);
+                queue.push_back(next);
+            }
+        }
+
+        Self {
+            sources,
+            states,
+            ignore_case,
+        }
+    }
+
+    fn step(&self, mut state: usize, c: char) -> usize {
+        let c = fold(c, self.ignore_case);
+        loop {
+            if let Some(next) = self.states[state].next.get(&c) {
+                return *next;
+            }
+            if state == 0 {
+                return 0;
+            }
+            state = self.states[state].fail;
+        }
+    }
+}
+
+// All matches of all patterns, overlapping ones included, ordered by where they end.
+// Matches ending at the same char come longest first.
+#[derive(Clone, Debug)]
+pub struct MultiFindIter<'a> {
+    chars: Chars<'a>,
+    patterns: MultiPattern,
+    state: usize,
+    position: usize,
+    pending: VecDeque<Match>,
+}
+
+impl<'a> MultiFindIter<'a> {
+    pub(crate) fn new(chars: Chars<'a>, patterns: MultiPattern) -> Self {
+        Self {
+            chars,
+            patterns,
+            state: 0,
+            position: 0,
+            pending: VecDeque::new(),
+        }
+    }
+}
+
+impl Iterator for MultiFindIter<'_> {
+    type Item = Match;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pending.is_empty() {
+            let c = self.chars.next()?;
+            self.position += 1;
+            self.state = self.patterns.step(self.state, c);
+
+            for id in &self.patterns.states[self.state].matches {
+                let len = self.patterns.sources[*id].len();
+                self.pending.push_back(Match {
+                    pattern: *id,
+                    range: self.position - len..self.position,
+                });
+            }
+        }
+        self.pending.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Match, MultiPattern, Pattern};
+    use crate::rope::rope::Rope;
+
+    fn lorem() -> Rope {
+        let text = "lorem ipsum dolor sit amet, ".repeat(200);
+        Rope::from_reader(text.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn find_first_and_last() {
+        let rope = Rope::from("Hello World, hello rope");
+
+        assert_eq!(Some(2), rope.find("llo"));
+        assert_eq!(Some(15), rope.rfind("llo"));
+        assert_eq!(None, rope.find("Rope"));
+        assert_eq!(None, rope.find(""));
+        assert_eq!(Some(0), rope.find("Hello World, hello rope"));
+        assert_eq!(None, rope.find("Hello World, hello rope!"));
+    }
+
+    #[test]
+    fn find_across_leaf_boundaries() {
+        let rope = lorem();
+        let text = rope.value();
+        assert!(rope.chunks().count() > 1);
+
+        let boundary = rope.chunks().next().unwrap().chars().count();
+        let needle: String = text.chars().skip(boundary - 3).take(7).collect();
+        let expected: Vec<_> = text
+            .match_indices(needle.as_str())
+            .map(|(idx, _)| idx..idx + 7)
+            .collect();
+
+        assert!(expected.contains(&(boundary - 3..boundary + 4)));
+        assert_eq!(
+            expected,
+            rope.find_iter(needle.as_str()).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn find_returns_char_offsets() {
+        let rope = Rope::from("zażółć gęślą jaźń, gęślą");
+
+        assert_eq!(Some(7), rope.find("gęślą"));
+        assert_eq!(Some(19), rope.rfind("gęślą"));
+        assert_eq!(
+            vec![7..12, 19..24],
+            rope.find_iter("gęślą").collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn find_ignoring_case() {
+        let rope = Rope::from("Zażółć GĘŚLĄ jaźń");
+        let pattern = Pattern::new("gęślą").ignore_case();
+
+        assert_eq!(None, rope.find("gęślą"));
+        assert_eq!(Some(7), rope.find(pattern.clone()));
+        assert_eq!(Some(7), rope.rfind(pattern));
+        assert_eq!(Some(0), rope.find(Pattern::new("zAŻ").ignore_case()));
+    }
+
+    #[test]
+    fn find_iter_does_not_overlap() {
+        let rope = Rope::from("aaaaa");
+
+        assert_eq!(vec![0..2, 2..4], rope.find_iter("aa").collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn find_in_slice() {
+        let rope = Rope::from("one two one two one");
+        let slice = rope.slice(4..15);
+
+        assert_eq!(Some(4), slice.find("one"));
+        assert_eq!(Some(8), slice.rfind("two"));
+        assert_eq!(None, slice.rfind("two one two one"));
+        assert_eq!(
+            vec![0..3, 8..11],
+            slice.find_iter("two").collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn find_all_patterns_at_once() {
+        let rope = Rope::from("she sells his shells");
+        let patterns = MultiPattern::new(&["he", "she", "his", "hers", "ell"]);
+
+        let matches: Vec<_> = rope.find_all(patterns).collect();
+
+        assert_eq!(
+            vec![
+                Match {
+                    pattern: 1,
+                    range: 0..3
+                },
+                Match {
+                    pattern: 0,
+                    range: 1..3
+                },
+                Match {
+                    pattern: 4,
+                    range: 5..8
+                },
+                Match {
+                    pattern: 2,
+                    range: 10..13
+                },
+                Match {
+                    pattern: 1,
+                    range: 14..17
+                },
+                Match {
+                    pattern: 0,
+                    range: 15..17
+                },
+                Match {
+                    pattern: 4,
+                    range: 16..19
+                },
+            ],
+            matches
+        );
+    }
+
+    #[test]
+    fn find_all_ignoring_case_across_leaves() {
+        let rope = lorem();
+        let patterns = MultiPattern::new(&["LOREM", "Amet", ""]).ignore_case();
+
+        let matches: Vec<_> = rope.find_all(patterns).collect();
+
+        assert_eq!(400, matches.len());
+        assert_eq!(0..5, matches[0].range);
+        assert_eq!(22..26, matches[1].range);
+        assert!(matches
+            .iter()
+            .all(|m| rope.slice(m.range.clone()).to_string().len() == [5, 4][m.pattern]));
+    }
+}
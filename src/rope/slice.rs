@@ -0,0 +1,479 @@
+use std::{
+    cmp::Ordering,
+    fmt,
+    hash::{Hash, Hasher},
+    io::{self, Write},
+    ops::{Bound, Range, RangeBounds},
+    ptr,
+};
+
+use super::{
+    error::{RopeError, RopeResult},
+    iter::{Bytes, Chars, Chunks},
+    node::Node,
+    regex::{self, Regex, RegexIter},
+    search::{self, FindIter, MultiFindIter, MultiPattern, Pattern},
+};
+
+// Content is hashed in blocks of this many bytes.
+const HASH_BLOCK: usize = 64;
+
+// Borrowed view into a char range of a rope. It shares the tree, so creating one is free.
+#[derive(Clone, Copy)]
+pub struct RopeSlice<'a> {
+    root: &'a Node,
+    start: usize,
+    end: usize,
+}
+
+impl<'a> RopeSlice<'a> {
+    pub(crate) fn new(root: &'a Node, range: Range<usize>) -> Self {
+        Self {
+            root,
+            start: range.start,
+            end: range.end,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    pub fn chunks(&self) -> Chunks<'a> {
+        Chunks::new(self.root, self.start..self.end)
+    }
+
+    pub fn chars(&self) -> Chars<'a> {
+        Chars::new(self.chunks())
+    }
+
+    pub fn bytes(&self) -> Bytes<'a> {
+        Bytes::new(self.chunks())
+    }
+
+    // Chars starting at given char index of this slice.
+    pub fn chars_at(&self, index: usize) -> Chars<'a> {
+        if index > self.len() {
+            panic!("Index out of bounds");
+        }
+        Chars::new(Chunks::new(self.root, self.start + index..self.end))
+    }
+
+    // Sub-slice, indexed relative to this slice.
+    pub fn slice<R: RangeBounds<usize>>(&self, range: R) -> RopeSlice<'a> {
+        self.try_slice(range)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn try_slice<R: RangeBounds<usize>>(&self, range: R) -> RopeResult<RopeSlice<'a>> {
+        let range = resolve_range(range, self.len())?;
+        Ok(RopeSlice::new(
+            self.root,
+            self.start + range.start..self.start + range.end,
+        ))
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.root.newlines_before(self.end) - self.root.newlines_before(self.start) + 1
+    }
+
+    // Returns char index (relative to this slice) at which given line starts.
+    // Passing `line_count()` returns `len()`.
+    pub fn line_to_char(&self, line: usize) -> usize {
+        let line_count = self.line_count();
+        if line > line_count {
+            panic!("Line out of bounds");
+        }
+
+        if line == 0 {
+            0
+        } else if line == line_count {
+            self.len()
+        } else {
+            let first_line = self.root.newlines_before(self.start);
+            self.root.char_after_newline(first_line + line) - self.start
+        }
+    }
+
+    // Returns index of the line (relative to this slice) containing char at given index.
+    pub fn char_to_line(&self, index: usize) -> usize {
+        if index > self.len() {
+            panic!("Index out of bounds");
+        }
+        self.root.newlines_before(self.start + index) - self.root.newlines_before(self.start)
+    }
+
+    // Returns given line, without the trailing line break.
+    pub fn line(&self, line: usize) -> RopeSlice<'a> {
+        let start = self.line_to_char(line);
+        let mut end = self.line_to_char(line + 1);
+        if line + 1 < self.line_count() {
+            end -= 1;
+        }
+        self.slice(start..end)
+    }
+
+    // Streams content of this slice to the writer chunk by chunk.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        for chunk in self.chunks() {
+            writer.write_all(chunk.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    // Char index of the first match of the pattern, relative to this slice.
+    pub fn find<P: Into<Pattern>>(&self, pattern: P) -> Option<usize> {
+        search::find(&pattern.into(), self.chars())
+    }
+
+    // Char index of the start of the last match, relative to this slice.
+    pub fn rfind<P: Into<Pattern>>(&self, pattern: P) -> Option<usize> {
+        search::rfind(&pattern.into(), self.chars().rev(), self.len())
+    }
+
+    pub fn find_iter<P: Into<Pattern>>(&self, pattern: P) -> FindIter<'a> {
+        FindIter::new(self.chars(), pattern.into())
+    }
+
+    // Every occurrence of every pattern in a single pass, overlapping ones included.
+    pub fn find_all(&self, patterns: MultiPattern) -> MultiFindIter<'a> {
+        MultiFindIter::new(self.chars(), patterns)
+    }
+
+    // Char range of the leftmost match of the regex, relative to this slice.
+    pub fn find_regex(&self, regex: &Regex) -> Option<Range<usize>> {
+        regex::find(regex, *self)
+    }
+
+    pub fn find_regex_iter<'r>(&self, regex: &'r Regex) -> RegexIter<'a, 'r> {
+        RegexIter::new(regex, *self)
+    }
+
+    // Last byte of the rope before this slice, used by regex anchors.
+    pub(crate) fn byte_before(&self) -> Option<u8> {
+        if self.start == 0 {
+            return None;
+        }
+        let chunk = Chunks::new(self.root, self.start - 1..self.start).next_back()?;
+        chunk.as_bytes().last().copied()
+    }
+
+    // First byte of the rope after this slice.
+    pub(crate) fn byte_after(&self) -> Option<u8> {
+        if self.end == self.root.len() {
+            return None;
+        }
+        let chunk = Chunks::new(self.root, self.end..self.end + 1).next()?;
+        chunk.as_bytes().first().copied()
+    }
+
+    fn eq_str(&self, other: &str) -> bool {
+        let mut rest = other.as_bytes();
+        for chunk in self.chunks() {
+            let chunk = chunk.as_bytes();
+            if rest.len() < chunk.len() || &rest[..chunk.len()] != chunk {
+                return false;
+            }
+            rest = &rest[chunk.len()..];
+        }
+        rest.is_empty()
+    }
+
+    // Compares content byte by byte, which for UTF-8 gives the same order as comparing chars.
+    // Chunks of the two slices don't have to line up.
+    pub(crate) fn compare(&self, other: &RopeSlice<'_>) -> Ordering {
+        if ptr::eq(self.root, other.root) && self.start == other.start && self.end == other.end {
+            return Ordering::Equal;
+        }
+
+        let (mut left, mut right) = (self.chunks(), other.chunks());
+        let (mut a, mut b): (&[u8], &[u8]) = (&[], &[]);
+        loop {
+            if a.is_empty() {
+                if let Some(chunk) = left.next() {
+                    a = chunk.as_bytes();
+                    continue;
+                }
+            }
+            if b.is_empty() {
+                if let Some(chunk) = right.next() {
+                    b = chunk.as_bytes();
+                    continue;
+                }
+            }
+            // One of them has ended, so the shorter one goes first.
+            if a.is_empty() || b.is_empty() {
+                return a.len().cmp(&b.len());
+            }
+
+            let common = a.len().min(b.len());
+            match a[..common].cmp(&b[..common]) {
+                Ordering::Equal => {}
+                ordering => return ordering,
+            }
+            a = &a[common..];
+            b = &b[common..];
+        }
+    }
+
+    // Feeds the hasher fixed-size blocks of content, so that equal slices hash the same no matter
+    // how their leaves are split.
+    fn hash_content<H: Hasher>(&self, state: &mut H) {
+        let mut block = [0; HASH_BLOCK];
+        let mut filled = 0;
+        for chunk in self.chunks() {
+            let mut bytes = chunk.as_bytes();
+            while !bytes.is_empty() {
+                let count = (HASH_BLOCK - filled).min(bytes.len());
+                block[filled..filled + count].copy_from_slice(&bytes[..count]);
+                filled += count;
+                bytes = &bytes[count..];
+                if filled == HASH_BLOCK {
+                    state.write(&block);
+                    filled = 0;
+                }
+            }
+        }
+        state.write(&block[..filled]);
+        // Same terminator as `str` uses, so that consecutive slices hashed together stay apart.
+        state.write_u8(0xff);
+    }
+}
+
+// Turns any range of chars into a bounded one, failing if it doesn't fit in `len`.
+pub(crate) fn resolve_range<R: RangeBounds<usize>>(
+    range: R,
+    len: usize,
+) -> RopeResult<Range<usize>> {
+    let start = match range.start_bound() {
+        Bound::Included(start) => *start,
+        Bound::Excluded(start) => *start + 1,
+        Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+        Bound::Included(end) => *end + 1,
+        Bound::Excluded(end) => *end,
+        Bound::Unbounded => len,
+    };
+
+    if start > end {
+        return Err(RopeError::InvalidRange { start, end });
+    }
+    if end > len {
+        return Err(RopeError::IndexOutOfBounds { index: end, len });
+    }
+    Ok(start..end)
+}
+
+impl fmt::Display for RopeSlice<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for chunk in self.chunks() {
+            f.write_str(chunk)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for RopeSlice<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("\"")?;
+        for chunk in self.chunks() {
+            write!(f, "{}", chunk.escape_debug())?;
+        }
+        f.write_str("\"")
+    }
+}
+
+impl PartialEq for RopeSlice<'_> {
+    fn eq(&self, other: &RopeSlice<'_>) -> bool {
+        self.len() == other.len() && self.compare(other) == Ordering::Equal
+    }
+}
+
+impl Eq for RopeSlice<'_> {}
+
+impl PartialOrd for RopeSlice<'_> {
+    fn partial_cmp(&self, other: &RopeSlice<'_>) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for RopeSlice<'_> {
+    fn cmp(&self, other: &RopeSlice<'_>) -> Ordering {
+        self.compare(other)
+    }
+}
+
+impl Hash for RopeSlice<'_> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.hash_content(state);
+    }
+}
+
+impl PartialEq<str> for RopeSlice<'_> {
+    fn eq(&self, other: &str) -> bool {
+        self.eq_str(other)
+    }
+}
+
+impl PartialEq<&str> for RopeSlice<'_> {
+    fn eq(&self, other: &&str) -> bool {
+        self.eq_str(other)
+    }
+}
+
+impl PartialEq<String> for RopeSlice<'_> {
+    fn eq(&self, other: &String) -> bool {
+        self.eq_str(other)
+    }
+}
+
+impl PartialEq<RopeSlice<'_>> for str {
+    fn eq(&self, other: &RopeSlice<'_>) -> bool {
+        other.eq_str(self)
+    }
+}
+
+impl PartialEq<RopeSlice<'_>> for &str {
+    fn eq(&self, other: &RopeSlice<'_>) -> bool {
+        other.eq_str(self)
+    }
+}
+
+impl PartialEq<RopeSlice<'_>> for String {
+    fn eq(&self, other: &RopeSlice<'_>) -> bool {
+        other.eq_str(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::hash::{DefaultHasher, Hash, Hasher};
+
+    use super::RopeSlice;
+    use crate::rope::rope::Rope;
+
+    #[test]
+    fn slice_whole_rope() {
+        let rope = Rope::from("Hello World");
+
+        let slice = rope.slice(..);
+
+        assert_eq!(11, slice.len());
+        assert_eq!("Hello World", slice);
+        assert_eq!("Hello World", slice.to_string());
+    }
+
+    #[test]
+    fn slice_in_the_middle() {
+        let rope = Rope::from("Zażółć").concat(Rope::from(" gęślą jaźń"));
+
+        let slice = rope.slice(3..10);
+
+        assert_eq!(7, slice.len());
+        assert_eq!("ółć gęś", slice);
+        assert_eq!("śęg ćłó", slice.chars().rev().collect::<String>());
+        assert_ne!("ółć gę", slice);
+        assert_ne!("ółć gęśl", slice);
+    }
+
+    #[test]
+    fn sub_slices() {
+        let rope = Rope::from("Hello beautiful World");
+
+        let slice = rope.slice(6..);
+        let sub = slice.slice(..9);
+
+        assert_eq!("beautiful World", slice);
+        assert_eq!("beautiful", sub);
+        assert_eq!("ful", sub.slice(6..=8));
+        assert!(sub.slice(3..3).is_empty());
+    }
+
+    #[test]
+    fn slice_across_leaves() {
+        let text = "lorem ipsum dolor sit amet, ".repeat(200);
+        let rope = Rope::from(text.as_str());
+
+        let slice = rope.slice(900..4100);
+
+        assert!(slice.chunks().count() > 1);
+        assert_eq!(&text[900..4100], slice);
+        assert!(slice.bytes().eq(text[900..4100].bytes()));
+    }
+
+    #[test]
+    fn lines_of_slice() {
+        let rope = Rope::from("first\nsecond\nthird\nfourth");
+
+        let slice = rope.slice(8..21);
+
+        assert_eq!(3, slice.line_count());
+        assert_eq!("cond", slice.line(0));
+        assert_eq!("third", slice.line(1));
+        assert_eq!("fo", slice.line(2));
+        assert_eq!(5, slice.line_to_char(1));
+        assert_eq!(1, slice.char_to_line(5));
+        assert_eq!(0, slice.char_to_line(4));
+    }
+
+    #[test]
+    fn write_slice_to_writer() {
+        let rope = Rope::from("first\nsecond\nthird");
+        let mut out = vec![];
+
+        rope.line(1).write_to(&mut out).unwrap();
+
+        assert_eq!(b"second", &out[..]);
+    }
+
+    #[test]
+    fn debug_prints_content() {
+        let rope = Rope::from("a\"b\n");
+
+        assert_eq!("\"a\\\"b\\n\"", format!("{:?}", rope.slice(..)));
+    }
+
+    #[test]
+    fn compare_slices_by_content() {
+        let rope = Rope::from("abcabd");
+        let other = Rope::from("ab").concat(Rope::from("c"));
+
+        assert_eq!(rope.slice(0..2), rope.slice(3..5));
+        assert_eq!(rope.slice(0..3), other.slice(..));
+        assert_ne!(rope.slice(0..3), rope.slice(3..6));
+        assert!(rope.slice(0..3) < rope.slice(3..6));
+        assert!(rope.slice(0..2) < rope.slice(3..6));
+        assert!(rope.slice(..) > other.slice(..));
+    }
+
+    #[test]
+    fn hash_does_not_depend_on_leaves() {
+        let text = "zażółć gęślą jaźń ".repeat(100);
+        let whole = Rope::from(text.as_str());
+        let (left, right) = text.split_at(text.len() / 2 + 1);
+        let joined = Rope::from(left).concat(Rope::from(right));
+        let leaves = |rope: &Rope| rope.chunks().map(str::len).collect::<Vec<_>>();
+        assert_ne!(leaves(&whole), leaves(&joined));
+
+        assert_eq!(hash(whole.slice(5..500)), hash(joined.slice(5..500)));
+        assert_ne!(hash(whole.slice(5..500)), hash(joined.slice(5..501)));
+    }
+
+    fn hash(slice: RopeSlice<'_>) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        slice.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    #[should_panic]
+    fn slice_out_of_bounds() {
+        let rope = Rope::from("Hello");
+        rope.slice(2..10);
+    }
+}
@@ -1,77 +1,483 @@
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 
-use super::{node::Node, rope::Rope};
+use super::{
+    error::{RopeError, RopeResult},
+    func::{insert, Context, NodeResult},
+    internal::Internal,
+    leaf::Leaf,
+    metrics::Metrics,
+    node::{Node, Weight},
+    rope::Rope,
+};
 
+// Cursor over a rope, implemented as a zipper. The current leaf is taken out of the tree and
+// every internal node above it is kept on a stack along with the branch taken, so moving to a
+// neighbouring leaf climbs only as high as needed, and edits at the cursor touch the current leaf
+// and counters of its ancestors without descending from the root again. The tree is put back
+// together when the cursor moves up, or in `into_rope`.
 #[derive(Clone, Debug)]
 pub struct Traverser {
-    curr_node: Arc<Node>,
-    level: usize,
-    prev: Option<Arc<Traverser>>,
+    // Ancestors of the current node, from the root down.
+    path: Vec<Frame>,
+    // Current node, taken out of its parent. Always a leaf between calls.
+    node: Arc<Node>,
+    // Char range covered by the current node, and line breaks before it.
+    start: usize,
+    end: usize,
+    lines: usize,
+    // Cursor position, as a char index into the whole rope.
+    index: usize,
+    len: usize,
+}
+
+#[derive(Clone, Debug)]
+struct Frame {
+    // Parent with a placeholder in place of the branch taken.
+    parent: Internal,
+    branch: usize,
+    start: usize,
+    end: usize,
+    lines: usize,
 }
 
 impl Traverser {
-    pub fn new(rope: &Rope) -> Self {
-        Self {
-            curr_node: rope.root.clone(),
-            level: 0,
-            prev: None,
+    // Cursor at the beginning of the rope.
+    pub fn new(rope: Rope) -> Self {
+        let len = rope.len();
+        let mut traverser = Self {
+            path: vec![],
+            node: rope.root,
+            start: 0,
+            end: len,
+            lines: 0,
+            index: 0,
+            len,
+        };
+        traverser.descend(0);
+        traverser
+    }
+
+    // Puts the tree back together.
+    pub fn into_rope(mut self) -> Rope {
+        self.take_rope()
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // Index of the line containing the cursor.
+    pub fn line(&self) -> usize {
+        self.lines + self.leaf().newlines_before(self.index - self.start)
+    }
+
+    // Content of the current leaf.
+    pub fn chunk(&self) -> &str {
+        self.leaf().as_str()
+    }
+
+    // Char index at which the current leaf starts.
+    pub fn chunk_start(&self) -> usize {
+        self.start
+    }
+
+    // Moves the cursor to given char index, climbing only as high as needed.
+    pub fn seek_char(&mut self, index: usize) {
+        self.try_seek_char(index)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn try_seek_char(&mut self, index: usize) -> RopeResult<()> {
+        if index > self.len {
+            return Err(RopeError::IndexOutOfBounds {
+                index,
+                len: self.len,
+            });
         }
+        self.seek(index);
+        Ok(())
     }
 
-    pub fn go_back(&self) -> Arc<Traverser> {
-        let arc = &self.prev;
-        match arc {
-            Some(prev) => {
-                println!("Going back");
-                prev.clone()
-            }
-            None => Arc::new(self.clone()),
+    // Moves the cursor to the beginning of given line. Passing the line count moves it to the end.
+    pub fn seek_line(&mut self, line: usize) {
+        self.try_seek_line(line)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn try_seek_line(&mut self, line: usize) -> RopeResult<()> {
+        while self.ascend() {}
+
+        let line_count = self.node.newlines() + 1;
+        let index = if line == 0 {
+            0
+        } else if line < line_count {
+            self.node.char_after_newline(line)
+        } else if line == line_count {
+            self.len
+        } else {
+            self.descend(self.index);
+            return Err(RopeError::IndexOutOfBounds {
+                index: line,
+                len: line_count,
+            });
+        };
+        self.descend(index);
+        Ok(())
+    }
+
+    // Moves the cursor to the beginning of the next leaf. Returns false if this is the last one.
+    pub fn next_leaf(&mut self) -> bool {
+        if self.end == self.len {
+            return false;
         }
+        self.seek(self.end);
+        true
     }
 
-    pub fn go_right(&self) -> Arc<Traverser> {
-        match &self.curr_node.as_ref() {
-            Node::Leaf(_) => Arc::new(self.clone()),
-            Node::Internal(internal) => {
-                let right = &internal.branches[1];
-                Arc::new(Traverser {
-                    curr_node: right.clone(),
-                    level: self.level + 1,
-                    prev: Some(Arc::new(self.clone())),
-                })
-            }
+    // Moves the cursor to the beginning of the previous leaf. Returns false if this is the first
+    // one.
+    pub fn prev_leaf(&mut self) -> bool {
+        if self.start == 0 {
+            return false;
         }
+        self.seek(self.start - 1);
+        self.index = self.start;
+        true
     }
 
-    pub fn go_left(&self) -> Arc<Traverser> {
-        match &self.curr_node.as_ref() {
-            Node::Leaf(_) => Arc::new(self.clone()),
-            Node::Internal(internal) => {
-                let right = &internal.branches[0];
-                Arc::new(Traverser {
-                    curr_node: right.clone(),
-                    level: self.level + 1,
-                    prev: Some(Arc::new(self.clone())),
-                })
-            }
+    // Inserts text at the cursor and moves the cursor right after it, like typing would.
+    pub fn insert(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+
+        self.cut_oversized();
+        let added = Metrics::of(text.as_bytes());
+        let offset = self.index - self.start;
+
+        let node = Arc::make_mut(&mut self.node);
+        let Node::Leaf(leaf) = node else {
+            unreachable!("Cursor always rests on a leaf");
+        };
+        if let NodeResult::NewNode(new_node) = insert(Context::new(offset, text), leaf) {
+            // Leaf overflowed and became a subtree. It gets balanced on the way up.
+            *node = new_node;
+        }
+
+        self.resize(added, Metrics::default());
+        self.descend(self.index + added.chars);
+    }
+
+    // Removes `count` chars right after the cursor.
+    pub fn remove(&mut self, count: usize) {
+        self.try_remove(count)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn try_remove(&mut self, count: usize) -> RopeResult<()> {
+        let end = self.index + count;
+        if end > self.len {
+            return Err(RopeError::IndexOutOfBounds {
+                index: end,
+                len: self.len,
+            });
+        }
+
+        self.cut_oversized();
+        let offset = self.index - self.start;
+        let leaf_len = self.leaf().weight();
+        // Leaves can't become empty, unless it's the only one.
+        let local = offset + count < leaf_len || (offset + count == leaf_len && offset > 0);
+
+        if local || self.path.is_empty() {
+            let Node::Leaf(leaf) = Arc::make_mut(&mut self.node) else {
+                unreachable!("Cursor always rests on a leaf");
+            };
+            let removed = leaf.remove_range(offset, offset + count);
+            self.resize(Metrics::default(), removed);
+        } else {
+            // Climbs to the lowest ancestor spanning the whole range, which keeps some of its
+            // content, as only the root may become empty. Rest of the tree stays untouched.
+            while !(self.start <= self.index && end <= self.end && count < self.end - self.start)
+                && self.ascend()
+            {}
+
+            let len = self.end - self.start;
+            let range = self.index - self.start..end - self.start;
+            let removed = Arc::make_mut(&mut self.node).remove_range(len, range);
+            self.resize(Metrics::default(), removed);
+            self.descend(self.index);
         }
+        Ok(())
     }
 
-    pub fn current(&self) {
-        match &self.curr_node.as_ref() {
-            Node::Leaf(leaf) => {
-                println!(
-                    "Current node is leaf on level {}. [Last index at: <{}>, Val: <{:?}>]",
-                    self.level, leaf.last_char_index, leaf.val
-                );
+    fn leaf(&self) -> &Leaf {
+        match self.node.as_ref() {
+            Node::Leaf(leaf) => leaf,
+            Node::Internal(_) => unreachable!("Cursor always rests on a leaf"),
+        }
+    }
+
+    // Cuts an oversized mapped leaf into regular ones before editing it, and goes down to the
+    // one under the cursor.
+    fn cut_oversized(&mut self) {
+        if self.leaf().is_oversized() {
+            Arc::make_mut(&mut self.node).cut_oversized();
+            self.descend(self.index);
+        }
+    }
+
+    fn seek(&mut self, index: usize) {
+        while !(self.start <= index && index < self.end) && self.ascend() {}
+        self.descend(index);
+    }
+
+    // Updates counters of the current node and of all of its ancestors after an edit at cursor.
+    fn resize(&mut self, added: Metrics, removed: Metrics) {
+        for frame in self.path.iter_mut() {
+            if frame.branch == 0 {
+                let metrics = frame.parent.left_metrics() + added - removed;
+                frame.parent.set_left_metrics(metrics);
             }
-            Node::Internal(_) => {
-                println!(
-                    "Current node is internal on level {}. Weight: {}.",
-                    self.level,
-                    self.curr_node.weight()
-                );
+            frame.end = frame.end + added.chars - removed.chars;
+        }
+        self.end = self.end + added.chars - removed.chars;
+        self.len = self.len + added.chars - removed.chars;
+    }
+
+    // Goes down from the current node to the leaf containing char at `index`.
+    fn descend(&mut self, index: usize) {
+        while let Node::Internal(_) = self.node.as_ref() {
+            let node = std::mem::replace(&mut self.node, hole());
+            let Node::Internal(mut parent) = Arc::unwrap_or_clone(node) else {
+                unreachable!();
+            };
+
+            let (start, end, lines) = (self.start, self.end, self.lines);
+            let branch = if index < start + parent.weight {
+                self.end = start + parent.weight;
+                0
+            } else {
+                self.start += parent.weight;
+                self.lines += parent.line_weight;
+                1
+            };
+            self.node = std::mem::replace(&mut parent.branches[branch], hole());
+            self.path.push(Frame {
+                parent,
+                branch,
+                start,
+                end,
+                lines,
+            });
+        }
+        self.index = index;
+    }
+
+    // Puts the current node back into its parent, which becomes the current node.
+    // Returns false at the root.
+    fn ascend(&mut self) -> bool {
+        let Some(frame) = self.path.pop() else {
+            return false;
+        };
+
+        let mut parent = frame.parent;
+        parent.branches[frame.branch] = std::mem::replace(&mut self.node, hole());
+        let mut node = Node::from(parent);
+        node.balance();
+
+        self.node = Arc::new(node);
+        self.start = frame.start;
+        self.end = frame.end;
+        self.lines = frame.lines;
+        true
+    }
+
+    fn take_rope(&mut self) -> Rope {
+        while self.ascend() {}
+        let root = std::mem::replace(&mut self.node, hole());
+        Rope::with_root(Arc::unwrap_or_clone(root), self.len)
+    }
+}
+
+// Placeholder for the branch taken out of its parent, shared to avoid allocating one per level.
+fn hole() -> Arc<Node> {
+    static HOLE: OnceLock<Arc<Node>> = OnceLock::new();
+    HOLE.get_or_init(|| Arc::new(Node::from(""))).clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Traverser;
+    use crate::rope::rope::Rope;
+
+    fn lorem() -> Rope {
+        let text = "lorem ipsum dolor sit amet\n".repeat(200);
+        Rope::from_reader(text.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn walk_over_all_leaves() {
+        let rope = lorem();
+        let expected: Vec<String> = rope.chunks().map(String::from).collect();
+        let mut traverser = Traverser::new(rope);
+
+        let mut chunks = vec![traverser.chunk().to_string()];
+        while traverser.next_leaf() {
+            assert_eq!(traverser.index(), traverser.chunk_start());
+            chunks.push(traverser.chunk().to_string());
+        }
+        assert_eq!(expected, chunks);
+
+        chunks.clear();
+        chunks.push(traverser.chunk().to_string());
+        while traverser.prev_leaf() {
+            chunks.push(traverser.chunk().to_string());
+        }
+        chunks.reverse();
+        assert_eq!(expected, chunks);
+        assert_eq!(0, traverser.index());
+    }
+
+    #[test]
+    fn seek_to_char_and_line() {
+        let mut traverser = Traverser::new(lorem());
+
+        traverser.seek_char(27 * 100 + 6);
+        assert_eq!(100, traverser.line());
+        assert!(
+            traverser.chunk()[traverser.index() - traverser.chunk_start()..].starts_with("ipsum")
+        );
+
+        traverser.seek_line(42);
+        assert_eq!(27 * 42, traverser.index());
+        assert_eq!(42, traverser.line());
+
+        traverser.seek_line(201);
+        assert_eq!(traverser.len(), traverser.index());
+        assert!(traverser.try_seek_line(202).is_err());
+        assert!(traverser.try_seek_char(traverser.len() + 1).is_err());
+        assert_eq!(traverser.len(), traverser.index());
+    }
+
+    #[test]
+    fn typing_at_cursor() {
+        let mut traverser = Traverser::new(lorem());
+
+        traverser.seek_line(100);
+        for c in "zażółć gęślą jaźń ".chars() {
+            traverser.insert(&c.to_string());
+        }
+        let rope = traverser.into_rope();
+
+        let expected = "lorem ipsum dolor sit amet\n".repeat(100)
+            + "zażółć gęślą jaźń "
+            + &"lorem ipsum dolor sit amet\n".repeat(100);
+        assert_eq!(expected, rope.value());
+        assert_eq!(201, rope.line_count());
+        assert_eq!(Ok(()), rope.check_invariants());
+    }
+
+    #[test]
+    fn typing_past_leaf_capacity() {
+        let mut traverser = Traverser::new(Rope::from("Hello World"));
+
+        traverser.seek_char(6);
+        for _ in 0..3000 {
+            traverser.insert("ą\n");
+        }
+        assert_eq!(6 + 6000, traverser.index());
+        assert_eq!(3000, traverser.line());
+        let rope = traverser.into_rope();
+
+        assert_eq!(format!("Hello {}World", "ą\n".repeat(3000)), rope.value());
+        assert_eq!(Ok(()), rope.check_invariants());
+    }
+
+    #[test]
+    fn removing_at_cursor() {
+        let mut traverser = Traverser::new(lorem());
+
+        traverser.seek_line(3);
+        traverser.remove(6);
+        traverser.seek_char(0);
+        traverser.remove(27);
+        // Spans several leaves.
+        traverser.seek_line(10);
+        traverser.remove(27 * 100);
+        let rope = traverser.into_rope();
+
+        let expected = "lorem ipsum dolor sit amet\n".repeat(2)
+            + "ipsum dolor sit amet\n"
+            + &"lorem ipsum dolor sit amet\n".repeat(96);
+        assert_eq!(expected, rope.value());
+        assert_eq!(Ok(()), rope.check_invariants());
+    }
+
+    #[test]
+    fn removing_across_neighbouring_leaves() {
+        let mut expected = "lorem ipsum dolor sit amet\n".repeat(200);
+        let mut traverser = Traverser::new(lorem());
+
+        for _ in 0..20 {
+            traverser.seek_char(traverser.chunk_start() + traverser.chunk().len() - 3);
+            let index = traverser.index();
+            traverser.remove(10);
+            expected.replace_range(index..index + 10, "");
+
+            assert_eq!(index, traverser.index());
+            assert!(!traverser.path.is_empty());
+            if !traverser.next_leaf() {
+                break;
             }
         }
+        let rope = traverser.into_rope();
+        assert_eq!(expected, rope.value());
+        assert_eq!(Ok(()), rope.check_invariants());
+
+        let mut traverser = Traverser::new(rope);
+        traverser.seek_char(1);
+        traverser.remove(traverser.len() - 2);
+        let rope = traverser.into_rope();
+        assert_eq!("l\n", rope.value());
+        assert_eq!(Ok(()), rope.check_invariants());
+    }
+
+    #[test]
+    fn edits_do_not_affect_shared_rope() {
+        let rope = lorem();
+        let mut traverser = Traverser::new(rope.clone());
+
+        traverser.seek_char(100);
+        traverser.insert("Hello");
+        traverser.remove(10);
+        let edited = traverser.into_rope();
+
+        assert_eq!("lorem ipsum dolor sit amet\n".repeat(200), rope.value());
+        assert_eq!(rope.len() - 5, edited.len());
+        assert_eq!(Ok(()), rope.check_invariants());
+        assert_eq!(Ok(()), edited.check_invariants());
+    }
+
+    #[test]
+    fn empty_rope() {
+        let mut traverser = Traverser::new(Rope::new());
+
+        assert!(traverser.is_empty());
+        assert!(!traverser.next_leaf());
+        assert!(!traverser.prev_leaf());
+        traverser.insert("Hello");
+        traverser.seek_char(0);
+        traverser.remove(5);
+
+        assert_eq!("", traverser.into_rope().value());
     }
 }